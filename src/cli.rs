@@ -0,0 +1,267 @@
+use std::{path::PathBuf, process::Command};
+
+use regex::Regex;
+
+use crate::core::FormatType;
+use crate::history::{parse_size, HistoryLimits};
+use crate::logs::Level;
+use crate::query::Query;
+use crate::source::{shell_command, Source};
+
+pub const USAGE: &str = "\
+Formats and colors logs of commands, files or piped input
+
+Usage:
+  log-parser [OPTIONS] -- <command> [args...]
+  log-parser [OPTIONS] <command> [args...]
+  log-parser [OPTIONS] --cmd <name=command>...
+  log-parser [OPTIONS] --file <path> | --follow <path> | -
+  log-parser [OPTIONS] --open <session>
+
+Sources (can be mixed):
+  --cmd <name=command>     Runs a command through the shell, labeling its logs with name
+  --file <path>            Reads a saved log file
+  --follow <path>          Keeps reading a file as it's written to, like tail -F
+  -                        Reads logs piped to stdin
+  --open <session>         Loads a session saved with --record or c:save
+
+Options:
+  --config <path>          Reads defaults from this file instead of the closest .log-parser.toml
+  --profile <name>         Applies a profile defined in the config
+  --format <mode>          compact, detailed (default) or raw
+  --parser <name>          Parses every stream with this parser first instead of detecting it
+  --level <level>          Hides logs below trace, debug, info, warn, error or fatal
+  --filter <regex>         Only shows logs matching the regex
+  --query <query>          Only shows logs whose fields match the query
+  --no-color               Disables colors (also disabled when NO_COLOR is set)
+  --history-size <lines>   How many lines are kept for replay (default 10000, 0 for no limit)
+  --history-bytes <size>   How many bytes are kept for replay, like 50MB
+  --record <path>          Writes every line to a session file as it arrives
+  --tui                    Shows logs in a full screen interface
+  -h, --help               Prints this help
+  -V, --version            Prints the version";
+
+/// Everything that can be set from the command line
+pub struct Options {
+    pub sources: Vec<Source>,
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    /// Settings left empty fall back to the config
+    pub format: Option<FormatType>,
+    pub parser: Option<String>,
+    pub min_level: Option<Level>,
+    pub filter: Option<Regex>,
+    pub query: Option<Query>,
+    pub color: bool,
+    pub tui: bool,
+    pub history: HistoryLimits,
+    pub record: Option<PathBuf>,
+    pub open: Option<PathBuf>,
+}
+
+/// What the command line asks for
+pub enum Cli {
+    Run(Box<Options>),
+    Help,
+    Version,
+}
+
+/// Parses the command line arguments (without the program name). Options go before the command,
+/// either separated by `--` or not, everything after the command's name is passed to it untouched
+pub fn parse(args: &[String]) -> Result<Cli, String> {
+    let mut options = Options {
+        sources: Vec::new(),
+        config: None,
+        profile: None,
+        format: None,
+        parser: None,
+        min_level: None,
+        filter: None,
+        query: None,
+        color: std::env::var_os("NO_COLOR").is_none(),
+        tui: false,
+        history: HistoryLimits::default(),
+        record: None,
+        open: None,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        i += 1;
+        // Both `--level warn` and `--level=warn` work
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || -> Result<String, String> {
+            if let Some(value) = inline_value.clone() {
+                return Ok(value);
+            }
+            let value = args
+                .get(i)
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", name))?;
+            i += 1;
+            Ok(value)
+        };
+
+        match name {
+            "-h" | "--help" => return Ok(Cli::Help),
+            "-V" | "--version" => return Ok(Cli::Version),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--profile" => options.profile = Some(value()?),
+            "--format" => {
+                options.format = Some(value()?.parse()?);
+            }
+            "--parser" => options.parser = Some(value()?),
+            "--level" => {
+                options.min_level = Some(value()?.parse()?);
+            }
+            "--filter" => {
+                let pattern = value()?;
+                options.filter = Some(
+                    Regex::new(&pattern).map_err(|e| format!("Invalid --filter regex: {}", e))?,
+                );
+            }
+            "--query" => {
+                let expr = value()?;
+                options.query =
+                    Some(Query::parse(&expr).map_err(|e| format!("Invalid query: {}", e))?);
+            }
+            "--no-color" => options.color = false,
+            "--tui" => options.tui = true,
+            "--history-size" => {
+                let lines = value()?;
+                let lines = lines
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid --history-size {}", lines))?;
+                // 0 means it's only bounded by bytes
+                options.history.max_lines = if lines == 0 { None } else { Some(lines) };
+            }
+            "--history-bytes" => {
+                options.history.max_bytes = Some(parse_size(&value()?)?);
+            }
+            "--record" => options.record = Some(PathBuf::from(value()?)),
+            "--open" => options.open = Some(PathBuf::from(value()?)),
+            "--cmd" => {
+                let value = value()?;
+                let (label, cmd) = value
+                    .split_once('=')
+                    .filter(|(label, cmd)| !label.trim().is_empty() && !cmd.trim().is_empty())
+                    .ok_or_else(|| format!("Expected --cmd name=command, got {}", value))?;
+                options.sources.push(Source::Command {
+                    label: label.trim().to_string(),
+                    command: shell_command(cmd),
+                });
+            }
+            "--file" => options.sources.push(Source::File(PathBuf::from(value()?))),
+            "--follow" => options
+                .sources
+                .push(Source::Follow(PathBuf::from(value()?))),
+            "-" => options.sources.push(Source::Stdin),
+            "--" => {
+                let program = args.get(i).ok_or("Missing command after --")?;
+                options
+                    .sources
+                    .push(command_source(program, &args[i + 1..]));
+                break;
+            }
+            unknown if unknown.starts_with('-') => {
+                return Err(format!("Unknown option {}", unknown));
+            }
+            program => {
+                options.sources.push(command_source(program, &args[i..]));
+                break;
+            }
+        }
+    }
+
+    // A session is enough to have something to look at
+    if options.sources.is_empty() && options.open.is_none() {
+        return Err("Missing command to run".to_string());
+    }
+    Ok(Cli::Run(Box::new(options)))
+}
+
+fn command_source(program: &str, args: &[String]) -> Source {
+    let mut command = Command::new(program);
+    command.args(args);
+    Source::Command {
+        label: program.to_string(),
+        command,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn options(line: &[&str]) -> Options {
+        match parse(&args(line)) {
+            Ok(Cli::Run(options)) => *options,
+            Ok(_) => panic!("{:?} didn't ask to run anything", line),
+            Err(e) => panic!("{:?} failed: {}", line, e),
+        }
+    }
+
+    fn error(line: &[&str]) -> String {
+        match parse(&args(line)) {
+            Err(e) => e,
+            Ok(_) => panic!("{:?} should have failed", line),
+        }
+    }
+
+    /// The program and arguments of the only source, which has to be a command
+    fn command(options: &Options) -> Vec<String> {
+        let [Source::Command { command, .. }] = options.sources.as_slice() else {
+            panic!("Expected a single command");
+        };
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn passes_everything_after_the_command_to_it() {
+        let with_separator = options(&["--no-color", "--", "npm", "run", "--level", "dev"]);
+        assert_eq!(
+            command(&with_separator),
+            vec!["npm", "run", "--level", "dev"]
+        );
+        assert!(!with_separator.color);
+        assert_eq!(with_separator.min_level, None);
+        let without = options(&["npm", "--tui"]);
+        assert_eq!(command(&without), vec!["npm", "--tui"]);
+        assert!(!without.tui);
+        assert_eq!(error(&["--"]), "Missing command after --");
+    }
+
+    #[test]
+    fn reads_values_after_the_option_or_its_equal_sign() {
+        let inline = options(&["--level=warn", "npm"]);
+        assert_eq!(inline.min_level, Some(Level::Warn));
+        let separate = options(&["--level", "warn", "npm"]);
+        assert_eq!(separate.min_level, Some(Level::Warn));
+        assert_eq!(command(&separate), vec!["npm"]);
+        let cmd = options(&["--cmd", "api=npm run dev"]);
+        let [Source::Command { label, .. }] = cmd.sources.as_slice() else {
+            panic!("Expected a single command");
+        };
+        assert_eq!(label, "api");
+    }
+
+    #[test]
+    fn reports_bad_arguments() {
+        assert_eq!(error(&["--level"]), "Missing value for --level");
+        assert_eq!(error(&["--verbose", "npm"]), "Unknown option --verbose");
+        assert_eq!(error(&[]), "Missing command to run");
+        assert_eq!(error(&["--no-color"]), "Missing command to run");
+        assert!(matches!(parse(&args(&["--help", "npm"])), Ok(Cli::Help)));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::core::{Context, FormatType, Stream};
+use crate::filter::parse_pattern;
+use crate::logs::{Level, Location};
+use crate::queries::DEFAULT_TOP;
+use crate::query::Query;
+use crate::session;
+use std::path::Path;
+
+// Every `c:` command, listed when an unknown one is typed
+const C_COMMANDS: [&str; 9] = [
+    "compact", "detailed", "raw", "focus", "frames", "replay", "save", "stats", "queries",
+];
+
+/// What should happen after running a command typed by the user
+pub enum Response {
+    /// Nothing to report (usually because the input wasn't a command)
+    Nothing,
+    /// Something to tell the user
+    Message(String),
+    /// Logs in history should be shown again with the current settings
+    Replay,
+    /// A frame of a stack trace should be opened in the editor
+    Open(Location),
+    Exit,
+}
+
+/// Runs a command typed in the interactive prompt (or in the TUI command line), updating the
+/// shared state. Printing the response is left to the caller
+pub fn execute(input: &str, context: &Context) -> Response {
+    let input = input.trim();
+    if input.to_lowercase() == "exit" {
+        return Response::Exit;
+    }
+    if input.to_lowercase() == "a" {
+        let lock = context.lock().unwrap();
+        let logs: Vec<_> = lock.history.iter().collect();
+        return Response::Message(format!("logs are {:#?}", logs));
+    }
+    if input.starts_with("c:") {
+        return match input.replacen("c:", "", 1).trim() {
+            "compact" => {
+                context.lock().unwrap().format_type = FormatType::Compact;
+                Response::Message("Setting mode to compact logs".to_string())
+            }
+            "detailed" => {
+                context.lock().unwrap().format_type = FormatType::Detailed;
+                Response::Message("Setting mode to detailed logs".to_string())
+            }
+            "raw" => {
+                context.lock().unwrap().format_type = FormatType::Raw;
+                Response::Message("Setting mode to raw logs".to_string())
+            }
+            cmd if cmd.starts_with("focus") => {
+                let label = cmd.trim_start_matches("focus").trim();
+                let mut lock = context.lock().unwrap();
+                if label.is_empty() || label == "all" {
+                    lock.focus = None;
+                    Response::Message("Showing logs from all processes".to_string())
+                } else if let Some(source) = lock.find_source(label) {
+                    lock.focus = Some(source);
+                    Response::Message(format!("Only showing logs from {}", label))
+                } else {
+                    Response::Message(format!("Unknown process {}", label))
+                }
+            }
+            "frames" => {
+                let mut lock = context.lock().unwrap();
+                lock.all_frames = !lock.all_frames;
+                Response::Message(
+                    if lock.all_frames {
+                        "Showing every frame of stack traces"
+                    } else {
+                        "Folding frames of the runtime in stack traces"
+                    }
+                    .to_string(),
+                )
+            }
+            "replay" => Response::Replay,
+            cmd if cmd.starts_with("save") => {
+                let path = cmd.trim_start_matches("save").trim();
+                if path.is_empty() {
+                    return Response::Message("Missing path to save the session to".to_string());
+                }
+                let lock = context.lock().unwrap();
+                match session::save(Path::new(path), &lock) {
+                    Ok(count) => Response::Message(format!("Saved {} lines to {}", count, path)),
+                    Err(e) => Response::Message(format!("Failed to save session: {}", e)),
+                }
+            }
+            "stats" => {
+                let lock = context.lock().unwrap();
+                let stats = lock.history.stats();
+                let mut message = format!(
+                    "History: {} lines ({} bytes), {} received, {} evicted ({} bytes)\nParsers:",
+                    lock.history.len(),
+                    lock.history.bytes(),
+                    stats.pushed,
+                    stats.evicted,
+                    stats.evicted_bytes
+                );
+                for parser in lock.parsers.stats() {
+                    message.push_str(&format!(
+                        "\n  {:<12} {:>8} lines  priority {}{}",
+                        parser.name,
+                        parser.matched,
+                        parser.priority,
+                        if parser.enabled { "" } else { " (disabled)" }
+                    ));
+                }
+                message.push_str(&format!(
+                    "\n  {:<12} {:>8} lines",
+                    "unmatched",
+                    lock.parsers.unmatched()
+                ));
+                if !lock.detections.is_empty() {
+                    message.push_str("\nDetected formats:");
+                }
+                for ((source, stream), detection) in &lock.detections {
+                    let label = lock
+                        .sources
+                        .get(*source)
+                        .map(|s| s.label.as_str())
+                        .unwrap_or_default();
+                    let stream = match stream {
+                        Stream::Stdout => "stdout",
+                        Stream::Stderr => "stderr",
+                    };
+                    message.push_str(&format!("\n  {} {}: {}", label, stream, detection));
+                }
+                Response::Message(message)
+            }
+            cmd if cmd.starts_with("queries") => {
+                let top = cmd.trim_start_matches("queries").trim();
+                let top = match top {
+                    "" => DEFAULT_TOP,
+                    top => match top.parse::<usize>() {
+                        Ok(top) if top > 0 => top,
+                        _ => return Response::Message(format!("Invalid number {}", top)),
+                    },
+                };
+                Response::Message(context.lock().unwrap().queries.report(top))
+            }
+            unknown => Response::Message(format!(
+                "Unknown command c: {}, expected one of {}",
+                unknown,
+                C_COMMANDS.join(", ")
+            )),
+        };
+    }
+    if let Some((command, pattern)) = input.split_once(':') {
+        if matches!(command, "f" | "x" | "h") {
+            let regex = match parse_pattern(pattern) {
+                Ok(regex) => regex,
+                Err(e) => return Response::Message(format!("Invalid pattern: {}", e)),
+            };
+            let mut lock = context.lock().unwrap();
+            let filter = &mut lock.text_filter;
+            let message = match (command, regex) {
+                ("f", None) => {
+                    filter.include = None;
+                    filter.exclude = None;
+                    "Clearing filters".to_string()
+                }
+                ("f", Some(regex)) => {
+                    let message = format!("Only showing logs matching {}", regex);
+                    filter.include = Some(regex);
+                    message
+                }
+                ("x", regex) => {
+                    let message = match &regex {
+                        Some(regex) => format!("Hiding logs matching {}", regex),
+                        None => "Clearing exclude filter".to_string(),
+                    };
+                    filter.exclude = regex;
+                    message
+                }
+                (_, regex) => {
+                    let message = match &regex {
+                        Some(regex) => format!("Highlighting {}", regex),
+                        None => "Clearing highlight".to_string(),
+                    };
+                    filter.highlight = regex;
+                    message
+                }
+            };
+            return Response::Message(message);
+        }
+    }
+    if input.starts_with("q:") {
+        let expr = input.replacen("q:", "", 1);
+        let expr = expr.trim();
+        if expr.is_empty() {
+            context.lock().unwrap().query = None;
+            return Response::Message("Clearing query".to_string());
+        }
+        return match Query::parse(expr) {
+            Ok(query) => {
+                let message = format!("Only showing logs matching {}", query);
+                context.lock().unwrap().query = Some(query);
+                Response::Message(message)
+            }
+            Err(e) => Response::Message(format!("Invalid query: {}", e)),
+        };
+    }
+    if input.starts_with("l:") {
+        let level = input.replacen("l:", "", 1);
+        let level = level.trim();
+        if level.is_empty() || level == "all" {
+            context.lock().unwrap().min_level = None;
+            return Response::Message("Showing logs of all levels".to_string());
+        }
+        return match level.parse::<Level>() {
+            Ok(level) => {
+                context.lock().unwrap().min_level = Some(level);
+                Response::Message(format!("Hiding logs below {}", level))
+            }
+            Err(e) => Response::Message(e),
+        };
+    }
+    if input.starts_with("p:") {
+        let cmd = input.replacen("p:", "", 1);
+        let (action, name) = cmd.trim().split_once(' ').unwrap_or((cmd.trim(), ""));
+        let enabled = match action {
+            "enable" => true,
+            "disable" => false,
+            _ => return Response::Message("Unknown parser command".to_string()),
+        };
+        let name = name.trim();
+        return match context.lock().unwrap().parsers.set_enabled(name, enabled) {
+            Ok(()) if enabled => Response::Message(format!("Enabled {} parser", name)),
+            Ok(()) => Response::Message(format!("Disabled {} parser", name)),
+            Err(e) => Response::Message(e),
+        };
+    }
+    if input.starts_with("o:") {
+        let frame = input.replacen("o:", "", 1);
+        let frame = frame.trim();
+        let lock = context.lock().unwrap();
+        let Some(stack) = lock.last_stack() else {
+            return Response::Message("No stack trace with frames to open".to_string());
+        };
+        let locations: Vec<Location> = stack
+            .locations()
+            .iter()
+            .map(|location| lock.links.local(location))
+            .collect();
+        if frame.is_empty() {
+            let mut message = "Frames of the last error:".to_string();
+            for (i, location) in locations.iter().enumerate() {
+                message.push_str(&format!("\n  {:>2} {}", i + 1, location));
+            }
+            return Response::Message(message);
+        }
+        return match frame.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= locations.len() => Response::Open(locations[n - 1].clone()),
+            _ => Response::Message(format!(
+                "Expected a frame between 1 and {}",
+                locations.len()
+            )),
+        };
+    }
+    if input.starts_with("s:") {
+        let filter = match input.replacen("s:", "", 1).trim() {
+            "stdout" => Some(Stream::Stdout),
+            "stderr" => Some(Stream::Stderr),
+            "all" => None,
+            _ => return Response::Message("Unknown stream".to_string()),
+        };
+        context.lock().unwrap().stream_filter = filter;
+        return Response::Message(match filter {
+            Some(s) => format!("Only showing logs from {:?}", s),
+            None => "Showing logs from all streams".to_string(),
+        });
+    }
+    Response::Nothing
+}
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::format::{Item, StrftimeItems};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::core::FormatType;
+use crate::filter::{Redaction, TextFilter};
+use crate::logs::{CustomFormat, JsonKeys, Level, Links, Parser, ParserRegistry, DEFAULT_LINK_URL};
+
+/// Name of the project config, looked up from the current directory upward
+pub const CONFIG_FILE_NAME: &str = ".log-parser.toml";
+
+/// Default settings as written in a config file. Everything is optional, what's missing is left to
+/// the command line or the built in defaults. Profiles are named sets of the same settings that
+/// are applied on top of the rest when picked with `--profile`
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// `compact`, `detailed` or `raw`
+    pub format: Option<String>,
+    /// Logs below this level are hidden
+    pub level: Option<String>,
+    /// Only logs matching this regex are shown
+    pub filter: Option<String>,
+    /// Logs matching this regex are hidden
+    pub exclude: Option<String>,
+    /// Matches of any of these regexes are highlighted
+    pub highlight: Vec<String>,
+    pub redact: Vec<RedactRule>,
+    /// strftime like format used for dates, like `%H:%M:%S%.3f`
+    pub timestamp_format: Option<String>,
+    /// Parsers tried on every line, in order. Parsers that aren't listed start disabled
+    pub parsers: Option<Vec<String>>,
+    /// A query running more times than this within one HTTP request is reported. 0 turns it off
+    pub n_plus_one: Option<usize>,
+    pub formats: Vec<FormatRule>,
+    pub json: JsonSettings,
+    pub links: LinkSettings,
+    pub profiles: HashMap<String, Settings>,
+}
+
+/// Replaces every match of `pattern` (tokens, emails...) before the line is shown or kept
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedactRule {
+    pub pattern: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+/// A log format described by a regex with named groups. `timestamp`, `level` and `msg` are shown
+/// like the ones of built in formats, any other group is kept as a field
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FormatRule {
+    pub name: String,
+    pub pattern: String,
+    /// strftime like format of the `timestamp` group. RFC 3339 when not set
+    pub timestamp_format: Option<String>,
+    /// Parsers with a higher priority are tried first (standard is 10, knex is 30). 20 by default
+    pub priority: Option<i32>,
+}
+
+/// Property names the json parser looks for, in order. Lists that aren't set keep the defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonSettings {
+    pub message: Option<Vec<String>>,
+    pub level: Option<Vec<String>>,
+    /// ISO 8601 strings or numbers since the epoch (seconds, milliseconds or nanoseconds)
+    pub timestamp: Option<Vec<String>>,
+    pub error: Option<Vec<String>>,
+}
+
+impl JsonSettings {
+    fn merge(self, other: JsonSettings) -> JsonSettings {
+        JsonSettings {
+            message: other.message.or(self.message),
+            level: other.level.or(self.level),
+            timestamp: other.timestamp.or(self.timestamp),
+            error: other.error.or(self.error),
+        }
+    }
+
+    fn resolve(self) -> JsonKeys {
+        let defaults = JsonKeys::default();
+        JsonKeys {
+            message: self.message.unwrap_or(defaults.message),
+            level: self.level.unwrap_or(defaults.level),
+            timestamp: self.timestamp.unwrap_or(defaults.timestamp),
+            error: self.error.unwrap_or(defaults.error),
+        }
+    }
+}
+
+/// Where frames of stack traces link to
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkSettings {
+    /// Like `vscode://file{path}:{line}:{column}`, `file://{path}` by default
+    pub url: Option<String>,
+    /// Prefixes of paths in logs (like `/app` inside a container) and the local path they're at
+    pub paths: HashMap<String, String>,
+}
+
+impl LinkSettings {
+    fn merge(mut self, other: LinkSettings) -> LinkSettings {
+        self.paths.extend(other.paths);
+        LinkSettings {
+            url: other.url.or(self.url),
+            paths: self.paths,
+        }
+    }
+
+    fn resolve(self) -> Result<Links, String> {
+        let url = self.url.unwrap_or_else(|| DEFAULT_LINK_URL.to_string());
+        if !url.contains("{path}") {
+            return Err(format!("Link url {} doesn't contain {{path}}", url));
+        }
+        let home = std::env::var("HOME").ok();
+        let paths = self
+            .paths
+            .into_iter()
+            .map(|(from, to)| match (to.strip_prefix("~/"), &home) {
+                (Some(rest), Some(home)) => (from, format!("{}/{}", home, rest)),
+                _ => (from, to),
+            })
+            .collect();
+        Ok(Links::new(url, paths))
+    }
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// Settings from config files, validated and ready to initialize the state
+#[derive(Debug)]
+pub struct Config {
+    pub format: Option<FormatType>,
+    pub min_level: Option<Level>,
+    pub text_filter: TextFilter,
+    pub redactions: Vec<Redaction>,
+    pub date_format: Option<String>,
+    pub parsers: ParserRegistry,
+    pub n_plus_one: Option<usize>,
+    pub links: Links,
+}
+
+impl Settings {
+    /// Combines two sets of settings, the ones in `other` win. Redaction rules add up instead, so
+    /// a more specific config can't accidentally drop one
+    fn merge(mut self, other: Settings) -> Settings {
+        self.redact.extend(other.redact);
+        // A format with the same name replaces the previous one
+        for format in other.formats {
+            self.formats.retain(|f| f.name != format.name);
+            self.formats.push(format);
+        }
+        self.profiles.extend(other.profiles);
+        Settings {
+            format: other.format.or(self.format),
+            level: other.level.or(self.level),
+            filter: other.filter.or(self.filter),
+            exclude: other.exclude.or(self.exclude),
+            highlight: if other.highlight.is_empty() {
+                self.highlight
+            } else {
+                other.highlight
+            },
+            redact: self.redact,
+            timestamp_format: other.timestamp_format.or(self.timestamp_format),
+            parsers: other.parsers.or(self.parsers),
+            n_plus_one: other.n_plus_one.or(self.n_plus_one),
+            formats: self.formats,
+            json: self.json.merge(other.json),
+            links: self.links.merge(other.links),
+            profiles: self.profiles,
+        }
+    }
+
+    /// Applies a profile on top of the rest of the settings
+    fn with_profile(mut self, name: &str) -> Result<Settings, String> {
+        let mut profiles = std::mem::take(&mut self.profiles);
+        match profiles.remove(name) {
+            Some(profile) => Ok(self.merge(profile)),
+            None if profiles.is_empty() => {
+                Err(format!("Unknown profile {}, none are defined", name))
+            }
+            None => {
+                let mut names: Vec<_> = profiles.keys().map(|n| n.as_str()).collect();
+                names.sort();
+                Err(format!(
+                    "Unknown profile {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    /// Checks every value, turning them into what the state needs
+    fn resolve(self) -> Result<Config, String> {
+        let pattern = |name: &str, pattern: &str| {
+            Regex::new(pattern).map_err(|e| format!("Invalid {} regex: {}", name, e))
+        };
+
+        let highlight = match self.highlight.as_slice() {
+            [] => None,
+            patterns => {
+                for p in patterns {
+                    pattern("highlight", p)?;
+                }
+                let combined: Vec<_> = patterns.iter().map(|p| format!("(?:{})", p)).collect();
+                Some(pattern("highlight", &combined.join("|"))?)
+            }
+        };
+
+        if let Some(format) = &self.timestamp_format {
+            check_time_format(format)?;
+        }
+
+        let built_in = Parser::built_in(self.json.resolve());
+        let mut formats = Vec::new();
+        for rule in self.formats {
+            if built_in.iter().any(|p| p.name() == rule.name) {
+                return Err(format!(
+                    "Format {} has the name of a built in parser",
+                    rule.name
+                ));
+            }
+            if let Some(format) = &rule.timestamp_format {
+                check_time_format(format)?;
+            }
+            let parser = Parser::Custom(Arc::new(CustomFormat {
+                pattern: pattern(&format!("{} format", rule.name), &rule.pattern)?,
+                name: rule.name,
+                timestamp_format: rule.timestamp_format,
+            }));
+            let priority = rule.priority.unwrap_or_else(|| parser.default_priority());
+            formats.push((parser, priority));
+        }
+        let available: Vec<(Parser, i32)> = built_in
+            .into_iter()
+            .map(|p| {
+                let priority = p.default_priority();
+                (p, priority)
+            })
+            .chain(formats)
+            .collect();
+
+        let mut parsers = ParserRegistry::new();
+        match self.parsers {
+            // Listed parsers are tried in that order, the rest are registered disabled so they can
+            // still be turned on with p:enable
+            Some(names) => {
+                for name in &names {
+                    if !available.iter().any(|(p, _)| p.name() == name) {
+                        let all: Vec<_> = available.iter().map(|(p, _)| p.name()).collect();
+                        return Err(format!(
+                            "Unknown parser {}, expected one of {}",
+                            name,
+                            all.join(", ")
+                        ));
+                    }
+                }
+                for (parser, _) in available {
+                    match names.iter().position(|name| name == parser.name()) {
+                        Some(i) => parsers.register(parser, ((names.len() - i) * 10) as i32, true),
+                        None => parsers.register(parser, 0, false),
+                    }
+                }
+            }
+            None => {
+                for (parser, priority) in available {
+                    parsers.register(parser, priority, true);
+                }
+            }
+        }
+
+        Ok(Config {
+            format: self.format.as_deref().map(str::parse).transpose()?,
+            min_level: self.level.as_deref().map(str::parse).transpose()?,
+            text_filter: TextFilter {
+                include: self.filter.map(|f| pattern("filter", &f)).transpose()?,
+                exclude: self.exclude.map(|x| pattern("exclude", &x)).transpose()?,
+                highlight,
+            },
+            redactions: self
+                .redact
+                .into_iter()
+                .map(|rule| {
+                    Ok(Redaction {
+                        pattern: pattern("redact", &rule.pattern)?,
+                        replacement: rule.replacement,
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+            date_format: self.timestamp_format,
+            parsers,
+            n_plus_one: self.n_plus_one,
+            links: self.links.resolve()?,
+        })
+    }
+}
+
+/// Loads the user config (`~/.config/log-parser/config.toml`) and the project config on top of
+/// it. The project config is the given path or the closest `.log-parser.toml` from the current
+/// directory upward. A profile, if given, has to be defined in one of them
+pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Config, String> {
+    let mut settings = match user_config_path().filter(|path| path.is_file()) {
+        Some(path) => read(&path)?,
+        None => Settings::default(),
+    };
+    let project = match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => find_project_config(),
+    };
+    if let Some(project) = project {
+        settings = settings.merge(read(&project)?);
+    }
+    if let Some(profile) = profile {
+        settings = settings.with_profile(profile)?;
+    }
+    settings.resolve()
+}
+
+fn check_time_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("Invalid timestamp format {}", format));
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Settings, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("log-parser").join("config.toml"))
+}
+
+fn find_project_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(toml: &str) -> Settings {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Names of the enabled parsers, in the order they're tried
+    fn enabled_parsers(config: &Config) -> Vec<String> {
+        config
+            .parsers
+            .stats()
+            .into_iter()
+            .filter(|s| s.enabled)
+            .map(|s| s.name)
+            .collect()
+    }
+
+    #[test]
+    fn resolves_every_setting() {
+        let config = settings(
+            r#"
+            format = "compact"
+            level = "warn"
+            filter = "users"
+            highlight = ["userId", "orderId"]
+            timestamp_format = "%H:%M:%S"
+            parsers = ["knex"]
+            n_plus_one = 10
+
+            [[redact]]
+            pattern = "token=\\w+"
+            "#,
+        )
+        .resolve()
+        .unwrap();
+        assert_eq!(config.format, Some(FormatType::Compact));
+        assert_eq!(config.min_level, Some(Level::Warn));
+        assert!(config
+            .text_filter
+            .include
+            .as_ref()
+            .unwrap()
+            .is_match("GET /users"));
+        let highlight = config.text_filter.highlight.as_ref().unwrap();
+        assert!(highlight.is_match("orderId") && highlight.is_match("userId"));
+        assert_eq!(config.redactions[0].replacement, "[REDACTED]");
+        assert_eq!(enabled_parsers(&config), vec!["knex"]);
+        assert_eq!(config.parsers.stats().len(), 4);
+        assert_eq!(config.n_plus_one, Some(10));
+    }
+
+    #[test]
+    fn project_settings_win_and_redactions_add_up() {
+        let user = settings(
+            r#"
+            format = "raw"
+            level = "debug"
+            [[redact]]
+            pattern = "password=\\S+"
+            "#,
+        );
+        let project = settings(
+            r#"
+            level = "error"
+            [[redact]]
+            pattern = "token=\\S+"
+            "#,
+        );
+        let config = user.merge(project).resolve().unwrap();
+        assert_eq!(config.format, Some(FormatType::Raw));
+        assert_eq!(config.min_level, Some(Level::Error));
+        assert_eq!(config.redactions.len(), 2);
+    }
+
+    #[test]
+    fn json_keys_keep_the_defaults_that_arent_set() {
+        let user = settings("[json]\nmessage = [\"event\"]");
+        let project = settings("[json]\nlevel = [\"severity\"]");
+        let keys = user.merge(project).json.resolve();
+        assert_eq!(keys.message, vec!["event"]);
+        assert_eq!(keys.level, vec!["severity"]);
+        assert_eq!(keys.timestamp, JsonKeys::default().timestamp);
+    }
+
+    #[test]
+    fn applies_profiles() {
+        let base = settings(
+            r#"
+            format = "detailed"
+            [profiles.api]
+            format = "compact"
+            level = "info"
+            "#,
+        );
+        let config = base.clone().with_profile("api").unwrap().resolve().unwrap();
+        assert_eq!(config.format, Some(FormatType::Compact));
+        assert_eq!(config.min_level, Some(Level::Info));
+        assert_eq!(
+            base.with_profile("web").unwrap_err(),
+            "Unknown profile web, expected one of api"
+        );
+    }
+
+    #[test]
+    fn orders_parsers_by_priority() {
+        let format = r#"
+            [[formats]]
+            name = "python"
+            pattern = '^(?P<level>\w+) (?P<msg>.*)$'
+            "#;
+        let config = settings(format).resolve().unwrap();
+        assert_eq!(
+            enabled_parsers(&config),
+            vec!["knex", "python", "logfmt", "standard", "json"]
+        );
+
+        let prioritized = format!("{}priority = 50", format);
+        let config = settings(&prioritized).resolve().unwrap();
+        assert_eq!(
+            enabled_parsers(&config),
+            vec!["python", "knex", "logfmt", "standard", "json"]
+        );
+
+        let ordered = format!("parsers = [\"standard\", \"python\"]\n{}", format);
+        let config = settings(&ordered).resolve().unwrap();
+        assert_eq!(enabled_parsers(&config), vec!["standard", "python"]);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(toml::from_str::<Settings>("colour = true").is_err());
+        assert!(settings("format = \"fancy\"").resolve().is_err());
+        assert!(settings("timestamp_format = \"%Q\"").resolve().is_err());
+        assert!(settings("parsers = [\"nope\"]").resolve().is_err());
+        assert!(settings("[links]\nurl = \"vscode://\"").resolve().is_err());
+        let shadowing = "[[formats]]\nname = \"knex\"\npattern = \".*\"";
+        assert!(settings(shadowing).resolve().is_err());
+    }
+}
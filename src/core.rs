@@ -1,31 +1,202 @@
 use std::{
-    io::{BufRead, BufReader},
-    process::ChildStdout,
-    sync::{Arc, Mutex},
+    collections::BTreeMap,
+    io::BufRead,
+    str::FromStr,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
 };
 
-use crate::logs;
+use crate::filter::{redact, Redaction, TextFilter};
+use crate::history::{History, HistorySize};
+use crate::logs::{
+    Detection, Detector, FormattedLog, Level, LineAssembler, Links, ParsableLog, ParserRegistry,
+    PrintOptions, StackTrace, Warning, FLUSH_AFTER,
+};
+use crate::queries::QueryStats;
+use crate::query::Query;
+use crate::requests::RequestTracker;
+use crate::session::Recorder;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
 use termcolor::Color;
 
-const LOG_REPLAY_CAPACITY: usize = 10000;
-// Useful to initialize empty array
-const INITIALIZER: String = String::new();
+// Colors used for the source labels, picked in order and wrapped around
+const LABEL_COLORS: [Color; 6] = [
+    Color::Green,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Blue,
+    Color::Red,
+    Color::Cyan,
+];
 
 #[derive(Debug)]
 pub struct CliState {
     pub format_type: FormatType,
-    pub last_logs: CircularBuffer,
+    /// Most recent lines, used for replay
+    pub history: History<LogLine>,
+    /// When set only lines coming from this stream are printed (live and on replay)
+    pub stream_filter: Option<Stream>,
+    /// Every source logs are read from. Lines point to their source by index
+    pub sources: Vec<SourceInfo>,
+    /// When set only lines coming from the source at this index are printed
+    pub focus: Option<usize>,
+    /// When set logs below this level are hidden. Logs without a level count as info
+    pub min_level: Option<Level>,
+    /// Regex filters and highlight set from the prompt
+    pub text_filter: TextFilter,
+    /// When set only logs whose fields match the query are shown
+    pub query: Option<Query>,
+    /// Whether logs are printed as they arrive. Turned off when the TUI takes over the screen
+    pub live_output: bool,
+    /// Something that went wrong while reading, waiting for the TUI to show it
+    pub notice: Option<String>,
+    /// How many lines were received since we started, including the ones no longer in history
+    pub received: usize,
+    /// When set every line is also written to a session file as it arrives
+    pub recorder: Option<Recorder>,
+    /// Log formats tried on every line
+    pub parsers: Arc<ParserRegistry>,
+    /// Index in the registry of the parser set with `--parser`, used instead of detecting the
+    /// format of each stream
+    pub forced_parser: Option<usize>,
+    /// The format detected for each stream of each source
+    pub detections: BTreeMap<(usize, Stream), Detection>,
+    /// Applied to every line as soon as it's read
+    pub redactions: Arc<Vec<Redaction>>,
+    /// How dates are shown next to logs
+    pub date_format: String,
+    /// Stack traces show the frames of the runtime itself instead of folding them (`c: frames`)
+    pub all_frames: bool,
+    /// Where frames of stack traces link to
+    pub links: Links,
+    /// Every database query seen since we started, even the ones no longer in history
+    pub queries: QueryStats,
+    /// Finds queries that run over and over within one HTTP request
+    pub requests: RequestTracker,
 }
 
 impl CliState {
-    pub fn insert_log(&mut self, log: String) {
-        self.last_logs.push(log);
+    /// Stack trace of the most recent log that has one with frames pointing to files
+    pub fn last_stack(&self) -> Option<StackTrace> {
+        self.history.iter().rev().find_map(|log| {
+            let stack = match &log.stack {
+                Some(stack) => Some(stack.as_ref().clone()),
+                None => log.parsed.as_ref()?.format_detailed().stack,
+            };
+            stack.filter(|s| !s.locations().is_empty())
+        })
+    }
+    /// Stores a log, followed by the warnings it led to (like N+1 queries). The warnings are
+    /// returned too, so they can be printed
+    pub fn insert_log(&mut self, log: LogLine) -> Vec<LogLine> {
+        let mut warnings = Vec::new();
+        if let Some(parsed) = &log.parsed {
+            if let Some(query) = parsed.query() {
+                self.queries.record(&query, log.received_at);
+            }
+            warnings = self.requests.observe(log.source, parsed.as_ref());
+        }
+        let (source, stream, received_at) = (log.source, log.stream, log.received_at);
+        self.history.push(log);
+        self.received += 1;
+        self.insert_warnings(warnings, source, stream, received_at)
     }
-    pub fn get_logs(&self) -> Vec<String> {
-        self.last_logs.get_all()
+    /// Ends the requests of a source that are still open, like when it stops logging. Returns
+    /// the warnings about them, which are stored like `insert_log` does
+    pub fn end_requests(&mut self, source: usize, stream: Stream) -> Vec<LogLine> {
+        let warnings = self.requests.finish(source);
+        self.insert_warnings(warnings, source, stream, Local::now())
+    }
+    fn insert_warnings(
+        &mut self,
+        warnings: Vec<Warning>,
+        source: usize,
+        stream: Stream,
+        received_at: DateTime<Local>,
+    ) -> Vec<LogLine> {
+        let warnings: Vec<LogLine> = warnings
+            .into_iter()
+            .map(|warning| LogLine::warning(warning, source, stream, received_at))
+            .collect();
+        for warning in &warnings {
+            self.history.push(warning.clone());
+            self.received += 1;
+        }
+        warnings
+    }
+    /// Tells the user about something that went wrong while reading. Printed right away unless
+    /// the TUI has the screen, which shows it in its input line instead
+    pub fn notify(&mut self, message: String) {
+        if self.live_output {
+            eprintln!("{}", message);
+        } else {
+            self.notice = Some(message);
+        }
+    }
+    /// Registers a new source and returns its index, which should be passed along with its lines
+    pub fn add_source(&mut self, label: &str) -> usize {
+        let index = self.sources.len();
+        self.sources.push(SourceInfo {
+            label: label.to_string(),
+            color: LABEL_COLORS[index % LABEL_COLORS.len()],
+        });
+        index
+    }
+    /// Finds the index of the source with the given label
+    pub fn find_source(&self, label: &str) -> Option<usize> {
+        self.sources.iter().position(|s| s.label == label)
+    }
+    /// Whether a line should be displayed with the current filters
+    pub fn should_show(&self, log: &LogLine) -> bool {
+        if let Some(s) = self.stream_filter {
+            if s != log.stream {
+                return false;
+            }
+        }
+        if let Some(focus) = self.focus {
+            if focus != log.source {
+                return false;
+            }
+        }
+        true
+    }
+    /// Whether a line passes the level threshold. Parsed logs without a level (like knex
+    /// queries) count as `info`. Lines we couldn't parse always pass: they have no level to judge
+    /// them by and they're often what matters most, like crashes written to stderr
+    pub fn level_passes(&self, log: &LogLine) -> bool {
+        let (Some(min), Some(parsed)) = (self.min_level, &log.parsed) else {
+            return true;
+        };
+        parsed.level().unwrap_or(Level::Info) >= min
+    }
+    /// The label that should be printed before a log. Labels are only useful when running more
+    /// than one source, so with a single one we don't print anything
+    pub fn label_for(&self, source: usize) -> Option<(String, Color)> {
+        if self.sources.len() < 2 {
+            return None;
+        }
+        let info = self.sources.get(source)?;
+        let width = self
+            .sources
+            .iter()
+            .map(|s| s.label.len())
+            .max()
+            .unwrap_or(0);
+        Some((format!("{:width$} |", info.label), info.color))
     }
 }
 
+/// Describes one of the places logs are coming from (usually a spawned command)
+#[derive(Debug)]
+pub struct SourceInfo {
+    pub label: String,
+    pub color: Color,
+}
+
 pub type Context = Arc<Mutex<CliState>>;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -35,120 +206,513 @@ pub enum FormatType {
     Raw,
 }
 
+impl FromStr for FormatType {
+    type Err = String;
 
-#[derive(Debug)]
-pub struct CircularBuffer {
-    buffer: [String; LOG_REPLAY_CAPACITY],
-    index: usize,
-    // Kinda bad to have this, there must be a better way, but it does the work
-    looped: bool,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "compact" => Ok(FormatType::Compact),
+            "detailed" => Ok(FormatType::Detailed),
+            "raw" => Ok(FormatType::Raw),
+            _ => Err(format!(
+                "Unknown format {}, expected compact, detailed or raw",
+                s
+            )),
+        }
+    }
 }
 
+/// The output stream of the child process a line was read from
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A line as it was read from a source, tagged with where and when it came from. Lines are parsed
+/// once when they arrive so replay and filters don't need to do it again. Stack traces are kept
+/// together with the line they follow, so a line can span several
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub line: String,
+    /// The first line parsed as one of the known log types, if it's one
+    pub parsed: Option<Arc<dyn ParsableLog>>,
+    /// Every line after the first one
+    pub stack: Option<Arc<StackTrace>>,
+    /// When the line was read, used as the date of logs that don't have one
+    pub received_at: DateTime<Local>,
+    /// Index of the source in `CliState::sources`
+    pub source: usize,
+    pub stream: Stream,
+    /// Added by us (like N+1 warnings) instead of read from a source. Left out of saved sessions,
+    /// they're found again when the session is opened
+    pub generated: bool,
+}
 
-impl CircularBuffer {
-    pub fn new() -> Self {
-        CircularBuffer {
-            buffer: [INITIALIZER; LOG_REPLAY_CAPACITY],
-            index: 0,
-            looped: false,
+impl LogLine {
+    /// A line that was just read and already parsed
+    pub fn new(
+        line: String,
+        parsed: Option<Arc<dyn ParsableLog>>,
+        stack: Option<StackTrace>,
+        source: usize,
+        stream: Stream,
+    ) -> LogLine {
+        LogLine {
+            line,
+            parsed,
+            stack: stack.map(Arc::new),
+            received_at: Local::now(),
+            source,
+            stream,
+            generated: false,
         }
     }
 
-    fn push(&mut self, item: String) {
-        self.buffer[self.index] = item;
-        if self.index + 1 >= LOG_REPLAY_CAPACITY {
-            self.looped = true;
+    /// Parses a line that was read at some point in the past (like when opening a session)
+    pub fn at(
+        line: String,
+        source: usize,
+        stream: Stream,
+        received_at: DateTime<Local>,
+        parsers: &ParserRegistry,
+    ) -> LogLine {
+        let (first, stack) = StackTrace::split(&line);
+        LogLine {
+            parsed: parsers.parse(first),
+            stack: stack.map(Arc::new),
+            line,
+            received_at,
+            source,
+            stream,
+            generated: false,
         }
-        self.index = (self.index + 1) % LOG_REPLAY_CAPACITY;
     }
 
-    fn get_all(&self) -> Vec<String> {
-        let mut vec = Vec::new();
-        let lower = self.buffer[0..self.index].to_vec();
-
-        // If we looped around at least once, then we need to start by the upper part of the array
-        if self.looped {
-            let upper = self.buffer[self.index..LOG_REPLAY_CAPACITY].to_vec();
-            for el in upper.iter() {
-                vec.push(el.to_string());
-            }
-        }
-        for el in lower.iter() {
-            vec.push(el.to_string());
+    /// A warning about the logs of a source, shown right after the ones it's about
+    pub fn warning(
+        warning: Warning,
+        source: usize,
+        stream: Stream,
+        received_at: DateTime<Local>,
+    ) -> LogLine {
+        LogLine {
+            line: warning.msg().to_string(),
+            parsed: Some(Arc::new(warning)),
+            stack: None,
+            received_at,
+            source,
+            stream,
+            generated: true,
         }
+    }
+}
 
-        return vec;
+/// An estimate of the memory taken by the line, its parsed record and its stack trace
+impl HistorySize for LogLine {
+    fn size(&self) -> usize {
+        let parsed = self.parsed.as_ref().map_or(0, |parsed| parsed.size());
+        let stack = self.stack.as_ref().map_or(0, |stack| {
+            stack
+                .lines
+                .iter()
+                .map(|line| line.len() + std::mem::size_of::<String>())
+                .sum()
+        });
+        std::mem::size_of::<LogLine>() + self.line.size() + parsed + stack
     }
 }
 
+/// A log that passed every filter and is ready to be shown
+pub enum DisplayedLog {
+    /// Raw mode shows lines exactly as they were read
+    Raw(String),
+    Formatted(FormattedLog),
+}
 
 /// Takes care of format and printing a line
-fn handle_line(line: &str, format_type: &FormatType) {
-    {
-        if *format_type == FormatType::Raw {
-            println!("{}", line);
-            return;
-        }
-    }
-    // println!("{:#?}", context);
-    if let Some(log) = logs::try_parse_known_log(line) {
-        let compact = match format_type {
-            FormatType::Compact => log.format_compact(),
-            FormatType::Detailed => log.format_detailed(),
-            FormatType::Raw => {
-                return;
+fn handle_line(log: &LogLine, state: &CliState) {
+    let Some(displayed) = display_line(log, state) else {
+        return;
+    };
+    let label = state.label_for(log.source);
+    match displayed {
+        DisplayedLog::Raw(line) => {
+            let line = match &label {
+                Some((label, _)) => format!("{} {}", label, line),
+                None => line,
+            };
+            // Keep the stream of origin so raw output can still be redirected like the child's
+            match log.stream {
+                Stream::Stdout => println!("{}", line),
+                Stream::Stderr => eprintln!("{}", line),
             }
+        }
+        DisplayedLog::Formatted(formatted) => formatted.print(&PrintOptions {
+            label: label.as_ref().map(|(text, color)| (text.as_str(), *color)),
+            highlight: state.text_filter.highlight.as_ref(),
+            date_format: Some(&state.date_format),
+            links: Some(&state.links),
+            all_frames: state.all_frames,
+        }),
+    }
+}
+
+/// Applies every filter to a line and formats it according to the current format mode. Returns
+/// nothing if the line should be hidden
+pub fn display_line(log: &LogLine, state: &CliState) -> Option<DisplayedLog> {
+    if !state.should_show(log) {
+        return None;
+    }
+    let line = log.line.as_str();
+    let parsed = &log.parsed;
+    if !state.level_passes(log) {
+        return None;
+    }
+    if let Some(query) = &state.query {
+        let matches = match parsed {
+            Some(parsed) => query.matches(parsed.fields()),
+            // Lines we couldn't parse only have a message
+            None => query.matches(&serde_json::json!({ "msg": line.trim_end() })),
         };
-        compact.print();
+        if !matches {
+            return None;
+        }
+    }
+    if state.format_type == FormatType::Raw {
+        if !state.text_filter.matches(line, None) {
+            return None;
+        }
+        return Some(DisplayedLog::Raw(line.trim_end().to_string()));
+    }
+    let mut formatted = if let Some(parsed) = parsed {
+        match state.format_type {
+            FormatType::Compact => parsed.format_compact(),
+            FormatType::Detailed => parsed.format_detailed(),
+            FormatType::Raw => {
+                return None;
+            }
+        }
     } else {
         // Here we find unknown logs. Usually not formatted in JSON or quick
         // console.logs.
         // We should probably integrate this in parse_known_log and support other than
         // compact logs
-        let compact = logs::FormattedLog {
-            date: chrono::Local::now(),
-            msg: line.clone().to_string(),
+        FormattedLog {
+            date: Some(log.received_at),
+            level: None,
+            msg: line.lines().next().unwrap_or("").to_string(),
+            msg_colors: Vec::new(),
             extra: None,
-            color_overwrite: Some(Color::Blue),
-        };
-        compact.print();
+            // Anything unstructured written to stderr is usually an uncaught exception or a
+            // warning, so make it stand out
+            color_overwrite: Some(match log.stream {
+                Stream::Stdout => Color::Blue,
+                Stream::Stderr => Color::Red,
+            }),
+            stack: None,
+        }
+    };
+    if let Some(stack) = &log.stack {
+        match state.format_type {
+            FormatType::Compact => formatted.msg = stack.summarize(&formatted.msg),
+            _ => formatted.stack = Some(stack.as_ref().clone()),
+        }
     }
+    if !state.text_filter.matches(line, Some(&formatted)) {
+        return None;
+    }
+    formatted.date.get_or_insert(log.received_at);
+    Some(DisplayedLog::Formatted(formatted))
 }
 
+/// A stream of a source being read: its format and the record being assembled
+struct StreamReader {
+    detector: Detector,
+    assembler: LineAssembler,
+}
 
-pub fn read_and_parse_logs(mut reader: BufReader<ChildStdout>, context: Context) {
-    // Buffer that will hold lines as they come
-    let mut buffer = String::new();
-    // Keep trying to get a new line in a loop
+/// Reads lines from the streams of a source until they're all closed. Every source runs this in
+/// its own thread, the lock on the context makes sure lines are stored and printed in the order
+/// they arrived. Stack traces are joined into a single line before parsing
+pub fn read_and_parse_logs(
+    streams: Vec<(Stream, Box<dyn BufRead + Send>)>,
+    source: usize,
+    context: Context,
+) {
+    // These don't change while running (parsers are turned on and off in place), so there's no need
+    // to lock for them on every line
+    let (parsers, redactions, forced_parser) = {
+        let lock = context.lock().unwrap();
+        (
+            lock.parsers.clone(),
+            lock.redactions.clone(),
+            lock.forced_parser,
+        )
+    };
+    let (sender, lines) = mpsc::channel();
+    let mut readers = BTreeMap::new();
+    for (stream, reader) in streams {
+        read_lines(reader, stream, sender.clone(), context.clone());
+        readers.insert(
+            stream,
+            StreamReader {
+                detector: Detector::new(&parsers, forced_parser),
+                assembler: LineAssembler::new(),
+            },
+        );
+    }
+    drop(sender);
+    let handle = |record: String, stream: Stream, detector: &mut Detector| {
+        handle_record(
+            record,
+            detector,
+            &parsers,
+            &redactions,
+            source,
+            stream,
+            &context,
+        )
+    };
     loop {
-        // Try to read a new line
+        // A trace that's being assembled is complete once the source goes quiet for a bit
+        let waiting = readers.values().any(|r| r.assembler.is_waiting());
+        let received = if waiting {
+            lines.recv_timeout(FLUSH_AFTER)
+        } else {
+            lines.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match received {
+            Ok((stream, line)) => {
+                for (other, reader) in readers.iter_mut() {
+                    // Whatever the other streams were assembling was written before this line
+                    if *other != stream {
+                        if let Some(record) = reader.assembler.flush() {
+                            handle(record, *other, &mut reader.detector);
+                        }
+                    }
+                }
+                if let Some(reader) = readers.get_mut(&stream) {
+                    for record in reader.assembler.push(line) {
+                        handle(record, stream, &mut reader.detector);
+                    }
+                }
+            }
+            Err(timeout_or_end) => {
+                for (stream, reader) in readers.iter_mut() {
+                    if let Some(record) = reader.assembler.flush() {
+                        handle(record, *stream, &mut reader.detector);
+                    }
+                }
+                if timeout_or_end == RecvTimeoutError::Disconnected {
+                    end_requests(source, Stream::Stdout, &context);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Reads lines in their own thread, so the ones that were read can be handled while waiting
+/// for the next
+fn read_lines(
+    mut reader: Box<dyn BufRead + Send>,
+    stream: Stream,
+    sender: Sender<(Stream, String)>,
+    context: Context,
+) {
+    thread::spawn(move || loop {
+        let mut buffer = String::new();
         match reader.read_line(&mut buffer) {
+            Ok(0) => return,
             Ok(_) => {
-                let line = buffer.as_str();
-                if line.len() == 0 {
+                if sender.send((stream, buffer)).is_err() {
                     return;
                 }
-                let format_type = {
-                    let mut lock = context.lock().unwrap();
-                    lock.insert_log(line.to_string());
-                    lock.format_type.clone()
-                };
-                handle_line(line, &format_type);
-                buffer.clear();
             }
             Err(e) => {
                 // Stop listening if something happened. Shouldn't be called
-                println!("{}", e);
-                break;
+                let message = format!("Stopped reading {:?}: {}", stream, e);
+                context.lock().unwrap().notify(message);
+                return;
             }
         }
+    });
+}
+
+/// Parses, prints, records and stores a complete line
+fn handle_record(
+    record: String,
+    detector: &mut Detector,
+    parsers: &ParserRegistry,
+    redactions: &[Redaction],
+    source: usize,
+    stream: Stream,
+    context: &Context,
+) {
+    // Parse before taking the lock so other readers aren't kept waiting
+    let line = redact(&record, redactions).into_owned();
+    let (first, stack) = StackTrace::split(&line);
+    let parsed = detector.parse(first, parsers);
+    let log = LogLine::new(line, parsed, stack, source, stream);
+    let detection = detector.updated(parsers);
+    let mut lock = context.lock().unwrap();
+    if let Some(detection) = detection {
+        lock.detections.insert((source, stream), detection);
+    }
+    if lock.live_output {
+        handle_line(&log, &lock);
+    }
+    if let Some(mut recorder) = lock.recorder.take() {
+        match recorder.record(&log, &lock) {
+            Ok(()) => lock.recorder = Some(recorder),
+            // Stop recording instead of failing on every line
+            Err(e) => lock.notify(format!("Failed to record session, stopping: {}", e)),
+        }
+    }
+    for warning in lock.insert_log(log) {
+        if lock.live_output {
+            handle_line(&warning, &lock);
+        }
+    }
+}
+
+/// Checks the requests of a source that are still open once it stops logging, printing what
+/// was found
+fn end_requests(source: usize, stream: Stream, context: &Context) {
+    let mut lock = context.lock().unwrap();
+    for warning in lock.end_requests(source, stream) {
+        if lock.live_output {
+            handle_line(&warning, &lock);
+        }
+    }
+}
+
+/// Checks the requests of every source that are still open, like before exiting
+pub fn end_all_requests(context: &Context) {
+    let sources = context.lock().unwrap().requests.sources();
+    for source in sources {
+        end_requests(source, Stream::Stdout, context);
     }
 }
 
 pub fn replay(context: &Context) {
     let lock = context.lock().unwrap();
-    for line in lock.get_logs() {
-        let format_type = { lock.format_type.clone() };
-        handle_line(line.as_str(), &format_type);
+    for log in lock.history.iter() {
+        handle_line(log, &lock);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::history::HistoryLimits;
+    use crate::logs::Parser;
+
+    /// A state like the one `main` builds, parsing knex and standard logs and reporting queries
+    /// that run more than once within a request
+    pub fn state() -> CliState {
+        let mut parsers = ParserRegistry::new();
+        parsers.register(Parser::Knex, 30, true);
+        parsers.register(Parser::Standard, 10, true);
+        CliState {
+            format_type: FormatType::Detailed,
+            history: History::new(HistoryLimits::default()),
+            stream_filter: None,
+            sources: Vec::new(),
+            focus: None,
+            min_level: None,
+            text_filter: TextFilter::default(),
+            query: None,
+            live_output: false,
+            notice: None,
+            received: 0,
+            recorder: None,
+            parsers: Arc::new(parsers),
+            forced_parser: None,
+            detections: BTreeMap::new(),
+            redactions: Arc::new(Vec::new()),
+            date_format: String::new(),
+            all_frames: false,
+            links: Links::default(),
+            queries: QueryStats::default(),
+            requests: RequestTracker::new(1),
+        }
+    }
+
+    fn line(state: &CliState, line: &str, stream: Stream) -> LogLine {
+        LogLine::at(line.to_string(), 0, stream, Local::now(), &state.parsers)
+    }
+
+    #[test]
+    fn hides_logs_below_the_level() {
+        let mut state = state();
+        let warn = line(&state, r#"{"level":40,"msg":"slow"}"#, Stream::Stdout);
+        let info = line(&state, r#"{"level":30,"msg":"hi"}"#, Stream::Stdout);
+        let query = line(&state, r#"{"sql":"select 1"}"#, Stream::Stdout);
+        let crash = line(&state, "TypeError: x is undefined", Stream::Stderr);
+        for log in [&warn, &info, &query, &crash] {
+            assert!(state.level_passes(log));
+        }
+        state.min_level = Some(Level::Warn);
+        assert!(state.level_passes(&warn));
+        assert!(!state.level_passes(&info));
+        // Logs without a level are info
+        assert!(!state.level_passes(&query));
+        // But lines that couldn't be parsed are always shown
+        assert!(state.level_passes(&crash));
+        state.min_level = Some(Level::Fatal);
+        assert!(!state.level_passes(&warn));
+        assert!(state.level_passes(&crash));
+    }
+
+    #[test]
+    fn replay_reuses_what_was_stored() {
+        let mut state = state();
+        let parsers = state.parsers.clone();
+        let at = Local::now() - chrono::Duration::minutes(5);
+        let pino = r#"{"level":30,"msg":"hi"}"#.to_string();
+        let log = LogLine::at(pino, 0, Stream::Stderr, at, &parsers);
+        let parsed = log.parsed.clone().unwrap();
+        state.insert_log(log);
+        state.insert_log(LogLine::at(
+            "plain".to_string(),
+            0,
+            Stream::Stdout,
+            at,
+            &parsers,
+        ));
+        let matched = |parsers: &ParserRegistry| -> Vec<usize> {
+            parsers.stats().iter().map(|s| s.matched).collect()
+        };
+        let before = (matched(&parsers), parsers.unmatched());
+
+        let context: Context = Arc::new(Mutex::new(state));
+        replay(&context);
+        let lock = context.lock().unwrap();
+        assert_eq!((matched(&parsers), parsers.unmatched()), before);
+        let stored: Vec<&LogLine> = lock.history.iter().collect();
+        assert!(Arc::ptr_eq(stored[0].parsed.as_ref().unwrap(), &parsed));
+        assert_eq!(
+            (stored[0].received_at, stored[0].stream),
+            (at, Stream::Stderr)
+        );
+        // Lines without a date of their own show when they arrived
+        let Some(DisplayedLog::Formatted(plain)) = display_line(stored[1], &lock) else {
+            panic!("Expected a formatted line");
+        };
+        assert_eq!(plain.date, Some(at));
+    }
+
+    #[test]
+    fn sizes_include_the_parsed_record() {
+        let state = state();
+        let text = r#"{"level":30,"msg":"hi","ids":[1,2,3,4,5,6,7,8]}"#;
+        let parsed = line(&state, text, Stream::Stdout);
+        let plain = line(&state, text.trim_start_matches('{'), Stream::Stdout);
+        assert!(parsed.parsed.is_some() && plain.parsed.is_none());
+        // The ids alone take more than their text once parsed
+        assert!(parsed.size() > plain.size() + 8 * std::mem::size_of::<serde_json::Value>());
     }
 }
@@ -0,0 +1,154 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+
+use crate::logs::FormattedLog;
+
+/// Text based filters set from the interactive prompt. Patterns are matched against the raw line
+/// and against the message and fields of the parsed log, so a filter works the same way no matter
+/// which format mode is being used
+#[derive(Debug, Default)]
+pub struct TextFilter {
+    /// Only logs matching this are shown
+    pub include: Option<Regex>,
+    /// Logs matching this are hidden
+    pub exclude: Option<Regex>,
+    /// Matches are highlighted in the output, doesn't hide anything
+    pub highlight: Option<Regex>,
+}
+
+impl TextFilter {
+    /// Whether a log should be shown. The formatted log is optional since lines that couldn't be
+    /// parsed (or raw mode) only have the raw line to match against
+    pub fn matches(&self, raw: &str, formatted: Option<&FormattedLog>) -> bool {
+        if self.include.is_none() && self.exclude.is_none() {
+            return true;
+        }
+        let fields = formatted
+            .and_then(|f| f.extra.as_ref())
+            .map(|extra| serde_json::to_string(extra).unwrap_or_default());
+        let haystacks = [
+            Some(raw),
+            formatted.map(|f| f.msg.as_str()),
+            fields.as_deref(),
+        ];
+        let any_match = |regex: &Regex| haystacks.iter().flatten().any(|h| regex.is_match(h));
+
+        if let Some(include) = &self.include {
+            if !any_match(include) {
+                return false;
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            if any_match(exclude) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parses a pattern typed in the prompt. An empty pattern means the filter should be cleared
+pub fn parse_pattern(pattern: &str) -> Result<Option<Regex>, regex::Error> {
+    let pattern = pattern.trim();
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+    Regex::new(pattern).map(Some)
+}
+
+/// Hides sensitive parts of a line (tokens, passwords...) before it's parsed, printed, kept in
+/// history or recorded
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    pub pattern: Regex,
+    /// What matches are replaced with. Can reference capture groups like `$1`
+    pub replacement: String,
+}
+
+/// Applies every redaction to a line, only allocating if something was replaced
+pub fn redact<'a>(line: &'a str, redactions: &[Redaction]) -> Cow<'a, str> {
+    let mut line = Cow::Borrowed(line);
+    for redaction in redactions {
+        if let Cow::Owned(replaced) = redaction
+            .pattern
+            .replace_all(&line, redaction.replacement.as_str())
+        {
+            line = Cow::Owned(replaced);
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn filter(include: Option<&str>, exclude: Option<&str>) -> TextFilter {
+        TextFilter {
+            include: include.map(|p| Regex::new(p).unwrap()),
+            exclude: exclude.map(|p| Regex::new(p).unwrap()),
+            highlight: None,
+        }
+    }
+
+    fn formatted(msg: &str, extra: Option<HashMap<String, serde_json::Value>>) -> FormattedLog {
+        FormattedLog {
+            date: None,
+            level: None,
+            msg: msg.to_string(),
+            msg_colors: Vec::new(),
+            extra,
+            color_overwrite: None,
+            stack: None,
+        }
+    }
+
+    #[test]
+    fn includes_and_excludes() {
+        let include = filter(Some("users"), None);
+        assert!(include.matches("GET /users", None));
+        assert!(!include.matches("GET /posts", None));
+        let exclude = filter(None, Some("health"));
+        assert!(exclude.matches("GET /users", None));
+        assert!(!exclude.matches("GET /healthcheck", None));
+        // Excluding wins over including
+        let both = filter(Some("GET"), Some("health"));
+        assert!(both.matches("GET /users", None));
+        assert!(!both.matches("GET /healthcheck", None));
+        assert!(!both.matches("POST /users", None));
+        assert!(TextFilter::default().matches("anything", None));
+    }
+
+    #[test]
+    fn matches_the_formatted_message_and_fields() {
+        let raw = r#"{"level":30,"msg":"created","data":{"userId":42}}"#;
+        let extra = HashMap::from([("user".to_string(), json!({ "email": "a@b.c" }))]);
+        let log = formatted("User created", Some(extra));
+        // Only the formatted message has it
+        assert!(filter(Some("User created"), None).matches(raw, Some(&log)));
+        assert!(!filter(Some("User created"), None).matches(raw, None));
+        // Only the raw line has it
+        assert!(filter(Some("userId"), None).matches(raw, Some(&log)));
+        // Only the fields have it
+        assert!(filter(Some("a@b"), None).matches(raw, Some(&log)));
+        assert!(!filter(None, Some("a@b")).matches(raw, Some(&log)));
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert!(parse_pattern("").unwrap().is_none());
+        assert!(parse_pattern("   ").unwrap().is_none());
+        assert_eq!(parse_pattern(" users ").unwrap().unwrap().as_str(), "users");
+        assert!(parse_pattern("users(").is_err());
+        // Case matters unless the pattern says otherwise
+        let sensitive = parse_pattern("error").unwrap().unwrap();
+        assert!(!sensitive.is_match("ERROR"));
+        let insensitive = parse_pattern("(?i)error").unwrap().unwrap();
+        assert!(insensitive.is_match("ERROR"));
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+
+/// Default number of lines kept when no limit is configured
+pub const DEFAULT_HISTORY_LINES: usize = 10000;
+
+/// Anything stored in history needs to tell how much memory it takes, so history can be bounded
+/// by bytes
+pub trait HistorySize {
+    fn size(&self) -> usize;
+}
+
+impl HistorySize for String {
+    fn size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// How much history should be kept. When both are set, whichever is reached first evicts the
+/// oldest entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryLimits {
+    pub max_lines: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        HistoryLimits {
+            max_lines: Some(DEFAULT_HISTORY_LINES),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Counters about what went through history since it was created
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HistoryStats {
+    pub pushed: usize,
+    pub evicted: usize,
+    pub evicted_bytes: usize,
+}
+
+/// Ring buffer keeping the most recent entries. Pushing is O(1) (amortized, evicting several old
+/// entries to make room for a big one is still constant per entry) and entries can be iterated in
+/// the order they were pushed without copying them
+#[derive(Debug)]
+pub struct History<T> {
+    entries: VecDeque<T>,
+    limits: HistoryLimits,
+    bytes: usize,
+    stats: HistoryStats,
+}
+
+impl<T: HistorySize> History<T> {
+    pub fn new(limits: HistoryLimits) -> Self {
+        History {
+            // Don't preallocate huge amounts of memory for absurd limits, the deque grows anyway
+            entries: VecDeque::with_capacity(
+                limits.max_lines.unwrap_or(0).min(DEFAULT_HISTORY_LINES),
+            ),
+            limits,
+            bytes: 0,
+            stats: HistoryStats::default(),
+        }
+    }
+
+    /// Adds an entry, evicting the oldest ones if limits are exceeded. The newest entry is always
+    /// kept, even if it's bigger than the byte limit by itself
+    pub fn push(&mut self, item: T) {
+        self.bytes += item.size();
+        self.entries.push_back(item);
+        self.stats.pushed += 1;
+
+        while self.entries.len() > 1 && self.over_limits() {
+            if let Some(evicted) = self.entries.pop_front() {
+                let size = evicted.size();
+                self.bytes -= size;
+                self.stats.evicted += 1;
+                self.stats.evicted_bytes += size;
+            }
+        }
+    }
+
+    fn over_limits(&self) -> bool {
+        self.limits
+            .max_lines
+            .is_some_and(|max| self.entries.len() > max)
+            || self.limits.max_bytes.is_some_and(|max| self.bytes > max)
+    }
+
+    /// Entries from oldest to newest
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    /// The last `count` entries (or less if there aren't that many), from oldest to newest
+    pub fn last(&self, count: usize) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries
+            .range(self.entries.len().saturating_sub(count)..)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Total size of the entries currently kept
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn stats(&self) -> HistoryStats {
+        self.stats
+    }
+}
+
+/// Parses a size like `500`, `64KB`, `10MB` or `1GB` into bytes
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let upper = size.to_uppercase();
+    let (number, multiplier) = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)]
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            upper
+                .strip_suffix(suffix)
+                .map(|number| (number.trim().to_string(), *multiplier))
+        })
+        .unwrap_or((upper.clone(), 1));
+    number
+        .parse::<usize>()
+        .map(|n| n * multiplier)
+        .map_err(|_| format!("Invalid size {}", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(limit: usize) -> History<String> {
+        History::new(HistoryLimits {
+            max_lines: Some(limit),
+            max_bytes: None,
+        })
+    }
+
+    fn contents(history: &History<String>) -> Vec<&str> {
+        history.iter().map(|s| s.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_insertion_order_before_filling_up() {
+        let mut history = lines(3);
+        history.push("a".to_string());
+        history.push("b".to_string());
+        assert_eq!(contents(&history), vec!["a", "b"]);
+        assert_eq!(history.stats().evicted, 0);
+    }
+
+    #[test]
+    fn wraps_around_dropping_the_oldest() {
+        let mut history = lines(3);
+        for item in ["a", "b", "c", "d", "e"] {
+            history.push(item.to_string());
+        }
+        assert_eq!(contents(&history), vec!["c", "d", "e"]);
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn wraps_around_several_times() {
+        let mut history = lines(3);
+        for i in 0..10 {
+            history.push(i.to_string());
+        }
+        assert_eq!(contents(&history), vec!["7", "8", "9"]);
+        assert_eq!(
+            history.stats(),
+            HistoryStats {
+                pushed: 10,
+                evicted: 7,
+                evicted_bytes: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn exactly_full_doesnt_evict() {
+        let mut history = lines(3);
+        for item in ["a", "b", "c"] {
+            history.push(item.to_string());
+        }
+        assert_eq!(contents(&history), vec!["a", "b", "c"]);
+        assert_eq!(history.stats().evicted, 0);
+    }
+
+    #[test]
+    fn evicts_by_bytes() {
+        let mut history = History::new(HistoryLimits {
+            max_lines: None,
+            max_bytes: Some(10),
+        });
+        history.push("aaaa".to_string());
+        history.push("bbbb".to_string());
+        assert_eq!(history.bytes(), 8);
+        history.push("cccc".to_string());
+        assert_eq!(contents(&history), vec!["bbbb", "cccc"]);
+        assert_eq!(history.bytes(), 8);
+        assert_eq!(history.stats().evicted_bytes, 4);
+    }
+
+    #[test]
+    fn whichever_limit_is_reached_first_wins() {
+        let mut history = History::new(HistoryLimits {
+            max_lines: Some(2),
+            max_bytes: Some(100),
+        });
+        for item in ["a", "b", "c"] {
+            history.push(item.to_string());
+        }
+        assert_eq!(contents(&history), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn keeps_an_entry_bigger_than_the_byte_limit() {
+        let mut history = History::new(HistoryLimits {
+            max_lines: None,
+            max_bytes: Some(4),
+        });
+        history.push("a".to_string());
+        history.push("way too big".to_string());
+        assert_eq!(contents(&history), vec!["way too big"]);
+    }
+
+    #[test]
+    fn last_returns_the_newest_entries_in_order() {
+        let mut history = lines(5);
+        for i in 0..7 {
+            history.push(i.to_string());
+        }
+        let last: Vec<&str> = history.last(2).map(|s| s.as_str()).collect();
+        assert_eq!(last, vec!["5", "6"]);
+        assert_eq!(history.last(100).len(), 5);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("64KB"), Ok(64 * 1024));
+        assert_eq!(parse_size("10mb"), Ok(10 * 1024 * 1024));
+        assert!(parse_size("lots").is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use std::ops::Range;
+
+use serde_json::Value;
+
+// Put around bindings by `interpolate_marked`. They're private use characters, so they never show
+// up in a query
+const BINDING_START: char = '\u{E000}';
+const BINDING_END: char = '\u{E001}';
+
+/// SQL flavors knex can log, which write placeholders, identifiers and literals differently
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    /// `$1`, `"identifier"`
+    Postgres,
+    /// `?`, `` `identifier` ``, backslashes escape in strings
+    MySql,
+    /// `?`, `"identifier"`, also what knex writes before compiling for a client
+    Sqlite,
+    /// `@p0`, `[identifier]`
+    MsSql,
+    /// `:1`, `"identifier"`
+    Oracle,
+}
+
+impl Dialect {
+    /// Knex doesn't log which client ran a query, so it's guessed from how the query is written
+    pub fn detect(sql: &str) -> Dialect {
+        let has_numbered = |prefix: &str| {
+            sql.match_indices(prefix).any(|(i, _)| {
+                sql[i + prefix.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_digit())
+            })
+        };
+        if has_numbered("$") {
+            Dialect::Postgres
+        } else if has_numbered("@p") {
+            Dialect::MsSql
+        } else if sql.contains('`') {
+            Dialect::MySql
+        } else if has_numbered(":") && !sql.contains('?') {
+            Dialect::Oracle
+        } else {
+            Dialect::Sqlite
+        }
+    }
+
+    fn quote_identifier(&self, identifier: &str) -> String {
+        // `table.column` is two identifiers
+        let parts: Vec<String> = identifier
+            .split('.')
+            .map(|part| match self {
+                Dialect::MySql => format!("`{}`", part.replace('`', "``")),
+                Dialect::MsSql => format!("[{}]", part.replace(']', "]]")),
+                _ => format!("\"{}\"", part.replace('"', "\"\"")),
+            })
+            .collect();
+        parts.join(".")
+    }
+
+    fn quote_string(&self, text: &str) -> String {
+        let text = match self {
+            Dialect::MySql => text.replace('\\', "\\\\"),
+            _ => text.to_string(),
+        };
+        format!("'{}'", text.replace('\'', "''"))
+    }
+
+    /// A binding written as a SQL literal. Dates are already strings in the logs, objects and
+    /// arrays are JSON (what knex sends for JSON columns) unless they're a serialized buffer
+    fn literal(&self, value: &Value) -> String {
+        match value {
+            Value::Null => "NULL".to_string(),
+            Value::Bool(b) => match self {
+                Dialect::MsSql | Dialect::Oracle => if *b { "1" } else { "0" }.to_string(),
+                _ => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            },
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => self.quote_string(s),
+            Value::Object(object) if object.get("type") == Some(&Value::from("Buffer")) => {
+                let hex: String = object
+                    .get("data")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_u64)
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                match self {
+                    Dialect::Postgres => format!("'\\x{}'", hex),
+                    Dialect::MsSql => format!("0x{}", hex),
+                    Dialect::Oracle => format!("HEXTORAW('{}')", hex),
+                    _ => format!("X'{}'", hex),
+                }
+            }
+            value => self.quote_string(&value.to_string()),
+        }
+    }
+}
+
+/// Replaces the placeholders of a query with its bindings, so it can be copied and run as is.
+/// Placeholders inside strings, quoted identifiers and comments are left alone, and so are the
+/// ones without a binding (missing bindings never fail). Every binding is put between markers so
+/// they can still be told apart after the query is formatted, `strip_marks` removes them
+pub fn interpolate_marked(sql: &str, bindings: Option<&Value>) -> String {
+    let dialect = Dialect::detect(sql);
+    let positional = bindings.and_then(Value::as_array);
+    let named = bindings.and_then(Value::as_object);
+    let mut next = 0;
+    let mut out = String::with_capacity(sql.len());
+    let chars: Vec<char> = sql.chars().collect();
+    let mut i = 0;
+
+    // Index of the first char after the identifier (or number) that starts at `start`
+    let word_end = |start: usize, digits_only: bool| {
+        let mut end = start;
+        while end < chars.len()
+            && (chars[end].is_ascii_digit()
+                || (!digits_only && (chars[end].is_alphanumeric() || chars[end] == '_')))
+        {
+            end += 1;
+        }
+        end
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next_char = chars.get(i + 1).copied();
+        match c {
+            // Copied as is until they're closed
+            '\'' | '"' | '`' => {
+                let end = closing_quote(&chars, i, c, dialect == Dialect::MySql && c == '\'');
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '[' if dialect == Dialect::MsSql => {
+                let end = closing_quote(&chars, i, ']', false);
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '-' if next_char == Some('-') => {
+                let end = (i..chars.len())
+                    .find(|j| chars[*j] == '\n')
+                    .unwrap_or(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '/' if next_char == Some('*') => {
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|j| chars[*j] == '*' && chars[j + 1] == '/')
+                    .map(|j| j + 2)
+                    .unwrap_or(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            // Escaped question marks are kept by knex as plain question marks
+            '\\' if next_char == Some('?') => {
+                out.push('?');
+                i += 2;
+            }
+            '?' => {
+                let identifier = next_char == Some('?');
+                let value = positional.and_then(|b| b.get(next));
+                next += 1;
+                let width = if identifier { 2 } else { 1 };
+                let text = match value {
+                    Some(Value::String(name)) if identifier => Some(dialect.quote_identifier(name)),
+                    Some(value) => Some(dialect.literal(value)),
+                    None => None,
+                };
+                match text {
+                    Some(text) => push_binding(&mut out, &text),
+                    None => out.extend(&chars[i..i + width]),
+                }
+                i += width;
+            }
+            '$' | '@' | ':' => {
+                // `$1` in Postgres, `@p0` in MSSQL, `:1` in Oracle and `:name` (or `:name:` for
+                // identifiers) with named bindings. `::` is a Postgres cast
+                let prefix = if c == '@' && next_char == Some('p') {
+                    2
+                } else {
+                    1
+                };
+                let start = i + prefix;
+                let numbered = matches!(
+                    (c, dialect, prefix),
+                    ('$', Dialect::Postgres, 1)
+                        | ('@', Dialect::MsSql, 2)
+                        | (':', Dialect::Oracle, 1)
+                );
+                let end = word_end(start, numbered);
+                let word: String = chars[start..end].iter().collect();
+                let is_cast = c == ':' && (next_char == Some(':') || i > 0 && chars[i - 1] == ':');
+                let replacement = if word.is_empty() || is_cast {
+                    None
+                } else if numbered {
+                    let index = word.parse::<usize>().ok();
+                    // `$1` and `:1` count from one, `@p0` from zero
+                    let index = if c == '@' {
+                        index
+                    } else {
+                        index.and_then(|i| i.checked_sub(1))
+                    };
+                    index
+                        .and_then(|index| positional?.get(index))
+                        .map(|value| (dialect.literal(value), end))
+                } else if c == ':' {
+                    let identifier = chars.get(end) == Some(&':');
+                    named.and_then(|named| named.get(&word)).map(|value| {
+                        match (identifier, value) {
+                            (true, Value::String(name)) => {
+                                (dialect.quote_identifier(name), end + 1)
+                            }
+                            _ => (dialect.literal(value), end),
+                        }
+                    })
+                } else {
+                    None
+                };
+                match replacement {
+                    Some((text, end)) => {
+                        push_binding(&mut out, &text);
+                        i = end;
+                    }
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Removes the markers added by `interpolate_marked`, returning where the bindings ended up. The
+/// formatter puts spaces around the markers, they're removed too
+pub fn strip_marks(text: &str) -> (String, Vec<Range<usize>>) {
+    let mut out = String::with_capacity(text.len());
+    let mut ranges = Vec::new();
+    let mut start = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            BINDING_START => {
+                chars.next_if_eq(&' ');
+                start = Some(out.len());
+            }
+            BINDING_END => {
+                let Some(start) = start.take() else {
+                    continue;
+                };
+                if out.len() > start && out.ends_with(' ') {
+                    out.pop();
+                }
+                ranges.push(start..out.len());
+            }
+            c => out.push(c),
+        }
+    }
+    (out, ranges)
+}
+
+fn push_binding(out: &mut String, text: &str) {
+    out.push(BINDING_START);
+    out.push_str(text);
+    out.push(BINDING_END);
+}
+
+/// Index right after the quote closing the one at `start`. Doubled quotes are escaped quotes
+fn closing_quote(chars: &[char], start: usize, quote: char, backslash_escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if backslash_escapes && chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn interpolate(sql: &str, bindings: Option<&Value>) -> String {
+        strip_marks(&interpolate_marked(sql, bindings)).0
+    }
+
+    #[test]
+    fn postgres() {
+        let sql =
+            r#"select * from "users" where "name" = $1 and "data"::text like $2 and "id" = $10"#;
+        assert_eq!(Dialect::detect(sql), Dialect::Postgres);
+        assert_eq!(
+            interpolate(sql, Some(&json!(["O'Brien", "%a%"]))),
+            r#"select * from "users" where "name" = 'O''Brien' and "data"::text like '%a%' and "id" = $10"#
+        );
+    }
+
+    #[test]
+    fn mysql() {
+        let sql = "select ?? from `users` where `path` = ? and `admin` = ? and `note` = '?'";
+        assert_eq!(Dialect::detect(sql), Dialect::MySql);
+        assert_eq!(
+            interpolate(sql, Some(&json!(["users.id", "C:\\tmp", true]))),
+            "select `users`.`id` from `users` where `path` = 'C:\\\\tmp' and `admin` = TRUE and `note` = '?'"
+        );
+    }
+
+    #[test]
+    fn sqlite() {
+        let sql =
+            "insert into \"files\" (\"data\", \"meta\", \"deleted_at\") values (?, ?, ?) -- ?";
+        assert_eq!(Dialect::detect(sql), Dialect::Sqlite);
+        assert_eq!(
+            interpolate(
+                sql,
+                Some(&json!([{"type": "Buffer", "data": [1, 255]}, {"tags": ["a"]}, null]))
+            ),
+            "insert into \"files\" (\"data\", \"meta\", \"deleted_at\") values (X'01ff', '{\"tags\":[\"a\"]}', NULL) -- ?"
+        );
+    }
+
+    #[test]
+    fn mssql() {
+        let sql = "select top (@p0) * from [users?] where [active] = @p1";
+        assert_eq!(Dialect::detect(sql), Dialect::MsSql);
+        assert_eq!(
+            interpolate(sql, Some(&json!([10, false]))),
+            "select top (10) * from [users?] where [active] = 0"
+        );
+    }
+
+    #[test]
+    fn oracle() {
+        let sql = "select * from \"users\" where \"id\" = :1 and \"created_at\" > :2";
+        assert_eq!(Dialect::detect(sql), Dialect::Oracle);
+        assert_eq!(
+            interpolate(sql, Some(&json!([1, "2024-01-31T10:00:00.000Z"]))),
+            "select * from \"users\" where \"id\" = 1 and \"created_at\" > '2024-01-31T10:00:00.000Z'"
+        );
+    }
+
+    #[test]
+    fn named_bindings() {
+        let sql = "select * from :table: where \"id\" = :id and \"at\" > now()::date";
+        assert_eq!(
+            interpolate(sql, Some(&json!({"table": "users", "id": 5}))),
+            "select * from \"users\" where \"id\" = 5 and \"at\" > now()::date"
+        );
+    }
+
+    #[test]
+    fn finds_bindings_after_formatting() {
+        let marked = interpolate_marked(
+            "select * from users where id = ? and x = 1",
+            Some(&json!([5])),
+        );
+        // What the formatter does to them
+        let formatted = marked
+            .replace('\u{E000}', "\u{E000} ")
+            .replace('\u{E001}', " \u{E001}");
+        let (text, ranges) = strip_marks(&formatted);
+        assert_eq!(text, "select * from users where id = 5 and x = 1");
+        assert_eq!(ranges, vec![31..32]);
+    }
+
+    #[test]
+    fn never_fails_on_missing_bindings() {
+        let sql = "select * from users where id = ? and name = ?";
+        assert_eq!(interpolate(sql, None), sql);
+        assert_eq!(interpolate(sql, Some(&json!("nope"))), sql);
+        assert_eq!(
+            interpolate(sql, Some(&json!([1]))),
+            "select * from users where id = 1 and name = ?"
+        );
+        assert_eq!(
+            interpolate("select 'never closed ?", None),
+            "select 'never closed ?"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::logs::time::parse_timestamp;
+use crate::logs::FormattedLog;
+use crate::logs::Level;
+use crate::logs::ParsableLog;
+use crate::logs::{field_value, strip_line_ending};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+// Groups with a special meaning, every other named group ends up in the log fields
+const TIMESTAMP_GROUP: &str = "timestamp";
+const LEVEL_GROUP: &str = "level";
+const MSG_GROUP: &str = "msg";
+
+/// A log format declared in the config as a regex with named groups. `timestamp`, `level` and
+/// `msg` are shown like the ones of known formats, any other group is kept as a field
+#[derive(Debug)]
+pub struct CustomFormat {
+    pub name: String,
+    pub pattern: Regex,
+    /// How the `timestamp` group is parsed (strftime like). RFC 3339 when not set
+    pub timestamp_format: Option<String>,
+}
+
+impl CustomFormat {
+    pub fn parse(&self, line: &str) -> Option<CustomLog> {
+        let line = strip_line_ending(line);
+        let captures = self.pattern.captures(line)?;
+
+        let mut fields = Map::new();
+        for name in self.pattern.capture_names().flatten() {
+            if let Some(value) = captures.name(name) {
+                fields.insert(name.to_string(), field_value(value.as_str()));
+            }
+        }
+        let group = |name: &str| captures.name(name).map(|m| m.as_str().trim());
+
+        Some(CustomLog {
+            date: group(TIMESTAMP_GROUP)
+                .and_then(|t| parse_timestamp(t, self.timestamp_format.as_deref())),
+            level: group(LEVEL_GROUP).and_then(|l| l.parse().ok()),
+            // Without a msg group the whole line is the message
+            msg: group(MSG_GROUP).unwrap_or(line).to_string(),
+            fields: Value::Object(fields),
+        })
+    }
+}
+
+/// A line matched by one of the custom formats
+#[derive(Debug)]
+pub struct CustomLog {
+    date: Option<DateTime<Local>>,
+    level: Option<Level>,
+    msg: String,
+    // Every named group that matched
+    fields: Value,
+}
+
+impl CustomLog {
+    /// Groups that aren't shown anywhere else
+    fn extra(&self) -> Option<HashMap<String, Value>> {
+        let extra: HashMap<String, Value> = self
+            .fields
+            .as_object()?
+            .iter()
+            .filter(|(name, _)| ![TIMESTAMP_GROUP, LEVEL_GROUP, MSG_GROUP].contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if extra.is_empty() {
+            None
+        } else {
+            Some(extra)
+        }
+    }
+}
+
+impl ParsableLog for CustomLog {
+    fn format_compact(&self) -> FormattedLog {
+        FormattedLog {
+            date: self.date,
+            level: self.level,
+            msg: self.msg.clone(),
+            msg_colors: Vec::new(),
+            extra: None,
+            color_overwrite: None,
+            stack: None,
+        }
+    }
+
+    fn format_detailed(&self) -> FormattedLog {
+        FormattedLog {
+            extra: self.extra(),
+            ..self.format_compact()
+        }
+    }
+
+    fn level(&self) -> Option<Level> {
+        self.level
+    }
+
+    fn fields(&self) -> &Value {
+        &self.fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    fn python() -> CustomFormat {
+        CustomFormat {
+            name: "python".to_string(),
+            pattern: Regex::new(
+                r"^(?P<timestamp>\S+ \S+) (?P<level>\w+) \[(?P<logger>[\w.]+)\] (?P<msg>.*)$",
+            )
+            .unwrap(),
+            timestamp_format: Some("%Y-%m-%d %H:%M:%S,%3f".to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_named_groups() {
+        let log = python()
+            .parse("2024-01-31 10:20:30,123 WARNING [app.db] Slow query took 1200ms\n")
+            .unwrap();
+        assert_eq!(log.level, Some(Level::Warn));
+        assert_eq!(log.msg, "Slow query took 1200ms");
+        let date = log.date.unwrap();
+        assert_eq!((date.hour(), date.minute(), date.second()), (10, 20, 30));
+        assert_eq!(log.fields["logger"], "app.db");
+        assert_eq!(
+            log.format_detailed()
+                .extra
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["logger"]
+        );
+    }
+
+    #[test]
+    fn ignores_lines_that_dont_match() {
+        assert!(python().parse(r#"{"level":30,"msg":"hi"}"#).is_none());
+    }
+
+    #[test]
+    fn keeps_numbers_as_numbers() {
+        let format = CustomFormat {
+            name: "access".to_string(),
+            pattern: Regex::new(r"(?P<status>\d+) (?P<path>\S+)").unwrap(),
+            timestamp_format: None,
+        };
+        let log = format.parse("500 /users").unwrap();
+        assert_eq!(log.fields["status"], 500);
+        assert_eq!(log.msg, "500 /users");
+        assert_eq!(log.date, None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::logs::{ParsableLog, ParserRegistry};
+
+/// How many lines of a stream are looked at before settling on its format
+pub const SAMPLE_LINES: usize = 20;
+
+/// Finds out which format a stream uses. Every parser is scored on the first lines (how many of
+/// them it understands) and the best one becomes the primary parser of the stream, tried first.
+/// Lines the primary parser doesn't understand still go through the rest of them, so streams
+/// mixing formats keep working
+#[derive(Debug)]
+pub struct Detector {
+    scores: Vec<usize>,
+    sampled: usize,
+    /// Index in the registry of the parser tried first
+    primary: Option<usize>,
+    /// Set with `--parser`, nothing is detected then
+    forced: bool,
+    /// Whether the detection changed since it was last reported
+    changed: bool,
+}
+
+/// What was found out about a stream so far, as shown by `c:stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub parser: Option<String>,
+    /// How many of the sampled lines the parser understood
+    pub matched: usize,
+    pub sampled: usize,
+    pub forced: bool,
+}
+
+impl Detector {
+    pub fn new(registry: &ParserRegistry, forced: Option<usize>) -> Detector {
+        Detector {
+            scores: vec![0; registry.len()],
+            sampled: 0,
+            primary: forced,
+            forced: forced.is_some(),
+            changed: true,
+        }
+    }
+
+    /// Parses a line with the primary parser first, scoring it while the stream is still being
+    /// sampled
+    pub fn parse(&mut self, line: &str, registry: &ParserRegistry) -> Option<Arc<dyn ParsableLog>> {
+        if self.is_sampling() && !line.trim().is_empty() {
+            let parsed = registry.score(line, &mut self.scores);
+            self.sampled += 1;
+            self.changed = true;
+            // The best so far is used right away, there's no need to wait for the whole sample.
+            // Ties go to the parser with the highest priority
+            self.primary = self
+                .scores
+                .iter()
+                .enumerate()
+                .filter(|(_, score)| **score > 0)
+                .max_by(|(a, a_score), (b, b_score)| a_score.cmp(b_score).then(b.cmp(a)))
+                .map(|(i, _)| i);
+            return registry.pick(line, parsed, self.primary);
+        }
+        if self.forced {
+            return registry.parse_forcing(line, self.primary);
+        }
+        registry.parse_preferring(line, self.primary)
+    }
+
+    fn is_sampling(&self) -> bool {
+        !self.forced && self.sampled < SAMPLE_LINES
+    }
+
+    /// The detection, only if it changed since the last time this was called
+    pub fn updated(&mut self, registry: &ParserRegistry) -> Option<Detection> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        Some(self.detection(registry))
+    }
+
+    pub fn detection(&self, registry: &ParserRegistry) -> Detection {
+        Detection {
+            parser: self
+                .primary
+                .and_then(|i| registry.name(i))
+                .map(|name| name.to_string()),
+            matched: self.primary.map(|i| self.scores[i]).unwrap_or(0),
+            sampled: self.sampled,
+            forced: self.forced,
+        }
+    }
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parser = self.parser.as_deref().unwrap_or("unknown");
+        if self.forced {
+            return write!(f, "{} (set with --parser)", parser);
+        }
+        write!(
+            f,
+            "{} ({} of {} lines{})",
+            parser,
+            self.matched,
+            self.sampled,
+            if self.sampled < SAMPLE_LINES {
+                " so far"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{CustomFormat, Parser};
+    use regex::Regex;
+
+    fn registry() -> ParserRegistry {
+        let mut registry = ParserRegistry::new();
+        registry.register(Parser::Knex, 30, true);
+        registry.register(Parser::Standard, 10, true);
+        registry
+    }
+
+    #[test]
+    fn picks_the_parser_matching_most_lines() {
+        let registry = registry();
+        let mut detector = Detector::new(&registry, None);
+        detector.parse(r#"{"sql":"select 1","bindings":[]}"#, &registry);
+        for _ in 0..3 {
+            detector.parse(r#"{"level":30,"msg":"hi"}"#, &registry);
+        }
+        detector.parse("plain text", &registry);
+        let detection = detector.detection(&registry);
+        assert_eq!(detection.parser.as_deref(), Some("standard"));
+        assert_eq!((detection.matched, detection.sampled), (3, 5));
+    }
+
+    #[test]
+    fn stops_sampling_after_enough_lines() {
+        let registry = registry();
+        let mut detector = Detector::new(&registry, None);
+        for _ in 0..SAMPLE_LINES {
+            detector.parse(r#"{"level":30,"msg":"hi"}"#, &registry);
+        }
+        for _ in 0..SAMPLE_LINES * 2 {
+            detector.parse(r#"{"sql":"select 1","bindings":[]}"#, &registry);
+        }
+        assert_eq!(
+            detector.detection(&registry).parser.as_deref(),
+            Some("standard")
+        );
+        // Lines of other formats are still parsed
+        assert_eq!(registry.stats()[0].matched, SAMPLE_LINES * 2);
+    }
+
+    #[test]
+    fn tries_the_detected_parser_first() {
+        let mut registry = registry();
+        let custom = CustomFormat {
+            name: "prefixed".to_string(),
+            pattern: Regex::new(r"^level=(?P<msg>.*)$").unwrap(),
+            timestamp_format: None,
+        };
+        registry.register(Parser::Custom(Arc::new(custom)), 20, true);
+        registry.register(Parser::Logfmt, 15, true);
+        let mut detector = Detector::new(&registry, None);
+        for _ in 0..3 {
+            detector.parse("msg=hi level=info", &registry);
+        }
+        // The custom format has a higher priority and matches too, but the stream is logfmt
+        let log = detector.parse("level=info msg=hi", &registry).unwrap();
+        assert_eq!(log.fields()["level"], "info");
+        // Lines of other formats are still parsed
+        let log = detector.parse(r#"{"level":30,"msg":"hi"}"#, &registry);
+        assert_eq!(log.unwrap().fields()["level"], 30);
+        let matched: Vec<_> = registry.stats().iter().map(|s| s.matched).collect();
+        assert_eq!(matched, vec![0, 0, 4, 1]);
+    }
+
+    #[test]
+    fn forced_parser_skips_detection() {
+        let registry = registry();
+        let mut detector = Detector::new(&registry, Some(0));
+        detector.parse(r#"{"level":30,"msg":"hi"}"#, &registry);
+        let detection = detector.detection(&registry);
+        assert_eq!(detection.parser.as_deref(), Some("knex"));
+        assert!(detection.forced);
+        assert_eq!(detection.sampled, 0);
+        assert!(detector.updated(&registry).is_some());
+        assert!(detector.updated(&registry).is_none());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::logs::time::{from_epoch, parse_timestamp};
+use crate::logs::FormattedLog;
+use crate::logs::Level;
+use crate::logs::ParsableLog;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use serde_json::Value;
+
+/// Names of the properties holding the message, level, time and error of a JSON log. The first
+/// key of each list found in a log is used
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonKeys {
+    pub message: Vec<String>,
+    pub level: Vec<String>,
+    pub timestamp: Vec<String>,
+    pub error: Vec<String>,
+}
+
+impl Default for JsonKeys {
+    /// Covers winston, bunyan like loggers, structlog, zap, logrus and GCP structured logs
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect();
+        JsonKeys {
+            message: keys(&["message", "msg"]),
+            level: keys(&["level", "severity", "lvl"]),
+            timestamp: keys(&["timestamp", "time", "ts", "@timestamp"]),
+            error: keys(&["error", "err", "exception"]),
+        }
+    }
+}
+
+/// JSON logs with a string level, like `{"level":"info","message":"hi","timestamp":"2024-..."}`.
+/// Pino logs (with a numeric level) are left to the standard parser
+#[derive(Debug)]
+pub struct JsonLog {
+    date: Option<DateTime<Local>>,
+    level: Option<Level>,
+    msg: String,
+    /// Message of the error attached to the log, if any
+    error: Option<String>,
+    /// Properties that aren't the message, level or time
+    rest: HashMap<String, Value>,
+    // The whole line as it was parsed
+    fields: Value,
+}
+
+impl JsonLog {
+    /// Parses JSON objects with a string message or a string level
+    pub fn from_line(line: &str, keys: &JsonKeys) -> Option<Self> {
+        let fields = serde_json::from_str::<Value>(line).ok()?;
+        let object = fields.as_object()?;
+        let find = |keys: &[String]| {
+            keys.iter()
+                .find_map(|key| object.get_key_value(key))
+                .map(|(key, value)| (key.as_str(), value))
+        };
+
+        let msg = find(&keys.message);
+        let level = find(&keys.level);
+        let is_string = |found: Option<(&str, &Value)>| found.is_some_and(|(_, v)| v.is_string());
+        // Numeric levels are pino's, which has its own parser
+        if level.is_some_and(|(_, l)| l.is_number()) || (!is_string(msg) && !is_string(level)) {
+            return None;
+        }
+
+        let shown: Vec<&str> = [msg, level, find(&keys.timestamp)]
+            .iter()
+            .flatten()
+            .map(|(key, _)| *key)
+            .collect();
+        let rest = object
+            .iter()
+            .filter(|(key, _)| !shown.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Some(JsonLog {
+            date: find(&keys.timestamp).and_then(|(_, t)| timestamp(t)),
+            level: level.and_then(|(_, l)| match l {
+                Value::String(l) => l.parse().ok(),
+                _ => None,
+            }),
+            msg: match msg {
+                Some((_, Value::String(msg))) => msg.clone(),
+                Some((_, msg)) => msg.to_string(),
+                None => String::new(),
+            },
+            error: find(&keys.error).and_then(|(_, e)| error_message(e)),
+            rest,
+            fields,
+        })
+    }
+}
+
+impl ParsableLog for JsonLog {
+    fn format_compact(&self) -> FormattedLog {
+        let msg = match &self.error {
+            Some(error) if self.msg.is_empty() => error.clone(),
+            Some(error) => format!("{} - {}", self.msg, error),
+            None => self.msg.clone(),
+        };
+        FormattedLog {
+            date: self.date,
+            level: self.level,
+            msg,
+            msg_colors: Vec::new(),
+            extra: None,
+            color_overwrite: None,
+            stack: None,
+        }
+    }
+
+    fn format_detailed(&self) -> FormattedLog {
+        FormattedLog {
+            msg: self.msg.clone(),
+            msg_colors: Vec::new(),
+            extra: if self.rest.is_empty() {
+                None
+            } else {
+                Some(self.rest.clone())
+            },
+            ..self.format_compact()
+        }
+    }
+
+    fn level(&self) -> Option<Level> {
+        self.level
+    }
+
+    fn fields(&self) -> &Value {
+        &self.fields
+    }
+}
+
+/// Times are either ISO 8601 strings or numbers since the epoch (in seconds, milliseconds or
+/// nanoseconds, told apart by their size)
+fn timestamp(value: &Value) -> Option<DateTime<Local>> {
+    match value {
+        Value::String(s) => parse_timestamp(s, None),
+        Value::Number(n) => from_epoch(n.as_f64()?),
+        _ => None,
+    }
+}
+
+/// The message of an error, which is either a string (usually with the stack) or an object
+fn error_message(error: &Value) -> Option<String> {
+    match error {
+        Value::String(s) => s.lines().next().map(|line| line.to_string()),
+        Value::Object(error) => match error.get("message").or_else(|| error.get("msg")) {
+            Some(Value::String(msg)) => Some(msg.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    fn parse(line: &str) -> Option<JsonLog> {
+        JsonLog::from_line(line, &JsonKeys::default())
+    }
+
+    #[test]
+    fn parses_winston_logs() {
+        let log = parse(
+            r#"{"level":"warn","message":"slow request","timestamp":"2024-01-31T10:20:30.000Z","path":"/users"}"#,
+        )
+        .unwrap();
+        assert_eq!(log.level, Some(Level::Warn));
+        assert_eq!(log.msg, "slow request");
+        assert!(log.date.is_some());
+        assert_eq!(log.rest.keys().collect::<Vec<_>>(), vec!["path"]);
+    }
+
+    #[test]
+    fn parses_epoch_times() {
+        // zap writes seconds with a fraction, other loggers milliseconds or nanoseconds
+        let seconds = parse(r#"{"level":"info","msg":"a","ts":1706696430.5}"#).unwrap();
+        let millis = parse(r#"{"level":"info","msg":"a","ts":1706696430500}"#).unwrap();
+        let nanos = parse(r#"{"level":"info","msg":"a","ts":1706696430500000000}"#).unwrap();
+        assert_eq!(seconds.date, millis.date);
+        assert_eq!(millis.date, nanos.date);
+        assert_eq!(seconds.date.unwrap().nanosecond(), 500_000_000);
+    }
+
+    #[test]
+    fn shows_the_error_message() {
+        let log = parse(
+            r#"{"level":"error","message":"request failed","error":{"message":"ECONNREFUSED","stack":"..."}}"#,
+        )
+        .unwrap();
+        assert_eq!(log.format_compact().msg, "request failed - ECONNREFUSED");
+    }
+
+    #[test]
+    fn uses_configured_keys() {
+        let keys = JsonKeys {
+            message: vec!["event".to_string()],
+            ..JsonKeys::default()
+        };
+        let log = JsonLog::from_line(r#"{"event":"user created","log_level":"x"}"#, &keys).unwrap();
+        assert_eq!(log.msg, "user created");
+    }
+
+    #[test]
+    fn leaves_pino_logs_alone() {
+        assert!(parse(r#"{"level":30,"msg":"hi","time":1706696430500}"#).is_none());
+        assert!(parse(r#"{"sql":"select 1"}"#).is_none());
+    }
+}
@@ -1,8 +1,13 @@
+use crate::logs::bindings::{interpolate_marked, strip_marks};
+use crate::logs::sql::highlight;
 use crate::logs::FormattedLog;
+use crate::logs::Level;
 use crate::logs::ParsableLog;
+use crate::logs::QueryEvent;
+use crate::logs::REQUEST_ID_KEYS;
 use std::collections::HashMap;
+use std::ops::Range;
 
-use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_with::skip_serializing_none;
@@ -15,68 +20,352 @@ use termcolor::Color;
 pub struct KnexLog {
     sql: String,
     bindings: Option<Value>,
+    /// `select`, `insert`, `raw`...
+    method: Option<Value>,
+    options: Option<Value>,
+    /// The connection that ran the query
+    #[serde(rename = "__knexUid")]
+    uid: Option<String>,
+    #[serde(rename = "__knexQueryUid")]
+    query_uid: Option<String>,
+    /// Set for every query of a transaction, including the ones starting and ending it
+    #[serde(rename = "__knexTxId")]
+    tx_id: Option<String>,
+    // The whole line as it was parsed, including properties we don't know about
+    #[serde(skip)]
+    fields: Value,
 }
+
+/// What a query does to the transaction it runs in
+#[derive(Debug, PartialEq)]
+enum TransactionStep {
+    Begin,
+    Commit,
+    Rollback,
+    /// Any other query
+    Query,
+}
+
+impl KnexLog {
+    /// Parses the JSON objects knex prints when debugging queries (with a `sql` property)
+    pub fn from_line(line: &str) -> Option<Self> {
+        let fields = serde_json::from_str::<Value>(line).ok()?;
+        let mut knex_log = KnexLog::deserialize(&fields).ok()?;
+        knex_log.fields = fields;
+        Some(knex_log)
+    }
+
+    /// Nested transactions are savepoints, so they start and end like one
+    fn step(&self) -> TransactionStep {
+        let sql = self.sql.trim().trim_end_matches(';').to_lowercase();
+        if sql.starts_with("begin")
+            || sql.starts_with("start transaction")
+            || sql.starts_with("savepoint")
+        {
+            TransactionStep::Begin
+        } else if sql.starts_with("commit") || sql.starts_with("release savepoint") {
+            TransactionStep::Commit
+        } else if sql.starts_with("rollback") {
+            TransactionStep::Rollback
+        } else {
+            TransactionStep::Query
+        }
+    }
+
+    /// Added when logging `query-error` events, either the message or the whole error. Read by
+    /// hand since lines can have both `error` and `err`
+    fn error(&self) -> Option<&Value> {
+        self.fields.get("error").or_else(|| self.fields.get("err"))
+    }
+
+    /// The database error of a failing query, with its code when there's one
+    fn error_message(&self) -> Option<String> {
+        match self.error()? {
+            Value::Null => None,
+            Value::String(message) => Some(message.clone()),
+            Value::Object(error) => {
+                let message = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| Value::Object(error.clone()).to_string());
+                Some(match error.get("code") {
+                    Some(Value::String(code)) => format!("{} ({})", message, code),
+                    Some(Value::Number(code)) => format!("{} ({})", message, code),
+                    _ => message,
+                })
+            }
+            error => Some(error.to_string()),
+        }
+    }
+
+    /// Transactions are shown as a bracket: a line opening it, its queries with a gutter and a
+    /// line closing it
+    fn in_transaction(&self, log: FormattedLog) -> FormattedLog {
+        let Some(tx_id) = &self.tx_id else {
+            return log;
+        };
+        let (marker, color) = match self.step() {
+            TransactionStep::Begin => ("┌ BEGIN", Color::Cyan),
+            TransactionStep::Commit => ("└ COMMIT", Color::Cyan),
+            TransactionStep::Rollback => ("└ ROLLBACK", Color::Red),
+            TransactionStep::Query => return with_gutter(log, &format!("│ {} ", tx_id)),
+        };
+        let (msg, color) = match self.error_message() {
+            Some(error) => (
+                format!("{} {} failed: {}", marker, tx_id, error),
+                Color::Red,
+            ),
+            None => (format!("{} {}", marker, tx_id), color),
+        };
+        FormattedLog {
+            msg,
+            msg_colors: Vec::new(),
+            color_overwrite: Some(color),
+            ..log
+        }
+    }
+}
+
+// Shown before the query in compact mode
+const COMPACT_HEADER: &str = "SQL query (might be different than the actual query):";
+
+/// Puts `gutter` (like `│ trx2 `, so queries of transactions running at the same time can be
+/// told apart) before every line of the message, keeping its colors in place
+fn with_gutter(log: FormattedLog, gutter: &str) -> FormattedLog {
+    let shift = |at: usize| gutter.len() * (1 + log.msg[..at].matches('\n').count());
+    let msg_colors: Vec<(Range<usize>, Color)> = log
+        .msg_colors
+        .iter()
+        .map(|(range, color)| {
+            (
+                range.start + shift(range.start)..range.end + shift(range.end),
+                *color,
+            )
+        })
+        .collect();
+    let mut msg = String::with_capacity(log.msg.len());
+    let mut gutters = Vec::new();
+    for (i, line) in log.msg.lines().enumerate() {
+        if i > 0 {
+            msg.push('\n');
+        }
+        gutters.push((msg.len()..msg.len() + gutter.len(), Color::Cyan));
+        msg.push_str(gutter);
+        msg.push_str(line);
+    }
+    // Ranges are kept in order
+    gutters.extend(msg_colors);
+    gutters.sort_by_key(|(range, _)| range.start);
+    let msg_colors = gutters;
+    FormattedLog {
+        msg,
+        msg_colors,
+        ..log
+    }
+}
+
 impl ParsableLog for KnexLog {
     fn format_compact(&self) -> FormattedLog {
-        let time = Local::now();
-        let bindings = self
-            .bindings
-            .clone()
-            .unwrap()
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|x| {
-                if x.is_string() {
-                    format!("'{}'", x.as_str().unwrap().to_string())
-                } else {
-                    x.to_string()
-                }
-            })
-            .collect();
-        let qs = QueryParams::Indexed(bindings);
-        let msg = sqlformat::format(
-            &self.sql.clone(),
-            &qs,
+        let sql = interpolate_marked(&self.sql, self.bindings.as_ref());
+        let formatted = sqlformat::format(
+            &sql,
+            &QueryParams::None,
             sqlformat::FormatOptions {
                 indent: Indent::Spaces(2),
                 uppercase: true,
                 lines_between_queries: 0,
             },
         );
-        FormattedLog {
-            date: time,
-            msg: format!(
-                "SQL query (might be different than the actual query):\n{}",
-                msg
-            ),
+        let (sql, bindings) = strip_marks(&formatted);
+        let error = self.error_message();
+        // Failing queries are all red, so they can't be missed
+        let (header, msg_colors) = match &error {
+            Some(error) => (format!("SQL query failed: {}", error), Vec::new()),
+            None => {
+                let offset = COMPACT_HEADER.len() + 1;
+                let mut msg_colors = vec![(0..COMPACT_HEADER.len(), Color::Yellow)];
+                msg_colors.extend(
+                    highlight(&sql, &bindings)
+                        .into_iter()
+                        .map(|(range, color)| (range.start + offset..range.end + offset, color)),
+                );
+                (COMPACT_HEADER.to_string(), msg_colors)
+            }
+        };
+        self.in_transaction(FormattedLog {
+            date: None,
+            level: self.level(),
+            msg: format!("{}\n{}", header, sql),
+            msg_colors,
             extra: None,
-            color_overwrite: Some(Color::Yellow),
-        }
+            color_overwrite: error.map(|_| Color::Red),
+            stack: None,
+        })
     }
 
-    fn from_line(line: &str) -> Option<Self> {
-        match serde_json::from_str::<KnexLog>(&line) {
-            Ok(r) => Some(r),
-            Err(_e) => {
-                // println!("ERRRRRR {}", e);
-                None
-            }
-        }
+    fn fields(&self) -> &Value {
+        &self.fields
     }
 
     fn format_detailed(&self) -> FormattedLog {
-        let time = Local::now();
         let msg = self.sql.clone();
         let mut bindings_map: HashMap<String, Value> = HashMap::new();
         if let Some(b) = &self.bindings {
             bindings_map.insert("bidings".to_string(), b.clone());
         }
-        FormattedLog {
-            date: time,
-            msg,
-            extra: Some(bindings_map),
-            color_overwrite: Some(Color::Blue),
+        let ids = [
+            ("connection", &self.uid),
+            ("query", &self.query_uid),
+            ("transaction", &self.tx_id),
+            (
+                "method",
+                &self
+                    .method
+                    .as_ref()
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+            ),
+        ];
+        for (key, value) in ids {
+            if let Some(value) = value {
+                bindings_map.insert(key.to_string(), Value::String(value.clone()));
+            }
+        }
+        if let Some(options) = self
+            .options
+            .as_ref()
+            .filter(|o| o.as_object().is_some_and(|o| !o.is_empty()))
+        {
+            bindings_map.insert("options".to_string(), options.clone());
+        }
+        if let Some(error) = self.error() {
+            bindings_map.insert("error".to_string(), error.clone());
         }
+        let error = self.error_message();
+        let color_overwrite = error.as_ref().map(|_| Color::Red);
+        self.in_transaction(FormattedLog {
+            date: None,
+            level: self.level(),
+            msg: match &error {
+                Some(error) => format!("{}\n{}", msg, error),
+                None => msg.clone(),
+            },
+            msg_colors: match error {
+                Some(_) => Vec::new(),
+                None => highlight(&msg, &[]),
+            },
+            extra: Some(bindings_map),
+            color_overwrite,
+            stack: None,
+        })
+    }
+
+    /// Only failing queries have a level, so they show up when hiding everything below errors
+    fn level(&self) -> Option<Level> {
+        self.error_message().map(|_| Level::Error)
+    }
+
+    fn query(&self) -> Option<QueryEvent<'_>> {
+        Some(QueryEvent {
+            sql: &self.sql,
+            uid: self.query_uid.as_deref(),
+            request_id: REQUEST_ID_KEYS
+                .iter()
+                .find_map(|key| self.fields.get(*key))
+                .and_then(Value::as_str),
+            // Not logged by knex itself, but usually added when logging `query-response` events
+            duration: self.fields.get("duration").and_then(Value::as_f64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn knex(line: &str) -> KnexLog {
+        KnexLog::from_line(line).unwrap()
+    }
+
+    #[test]
+    fn brackets_transactions() {
+        let begin = knex(r#"{"sql":"BEGIN;","__knexUid":"__knexUid1","__knexTxId":"trx2"}"#);
+        assert_eq!(begin.format_compact().msg, "┌ BEGIN trx2");
+        let query =
+            knex(r#"{"method":"select","sql":"select ?","bindings":[1],"__knexTxId":"trx2"}"#);
+        let formatted = query.format_compact();
+        assert_eq!(
+            formatted.msg,
+            format!("│ trx2 {}\n│ trx2 SELECT\n│ trx2   1", COMPACT_HEADER)
+        );
+        // Colors follow the text they were for
+        let (number, _) = formatted.msg_colors.last().unwrap();
+        assert_eq!(&formatted.msg[number.clone()], "1");
+        let savepoint = knex(r#"{"sql":"ROLLBACK TO SAVEPOINT trx3","__knexTxId":"trx3"}"#);
+        assert_eq!(savepoint.format_compact().msg, "└ ROLLBACK trx3");
+        let detailed = query.format_detailed();
+        assert_eq!(detailed.extra.unwrap()["transaction"], "trx2");
+    }
+
+    #[test]
+    fn tells_interleaved_transactions_apart() {
+        let lines = [
+            r#"{"sql":"BEGIN;","__knexTxId":"trx2"}"#,
+            r#"{"sql":"BEGIN;","__knexTxId":"trx3"}"#,
+            r#"{"sql":"select 1","__knexTxId":"trx3"}"#,
+            r#"{"sql":"select 2","__knexTxId":"trx2"}"#,
+            r#"{"sql":"COMMIT;","__knexTxId":"trx2"}"#,
+            r#"{"sql":"ROLLBACK","__knexTxId":"trx3"}"#,
+        ];
+        let shown: Vec<String> = lines
+            .iter()
+            .map(|line| knex(line).format_detailed().msg)
+            .collect();
+        assert_eq!(
+            shown,
+            vec![
+                "┌ BEGIN trx2",
+                "┌ BEGIN trx3",
+                "│ trx3 select 1",
+                "│ trx2 select 2",
+                "└ COMMIT trx2",
+                "└ ROLLBACK trx3",
+            ]
+        );
+        let formatted = knex(lines[2]).format_detailed();
+        assert_eq!(formatted.msg_colors[0], (0.."│ trx3 ".len(), Color::Cyan));
+        assert_eq!(
+            formatted.msg_colors[1].0,
+            "│ trx3 ".len().."│ trx3 select".len()
+        );
+    }
+
+    #[test]
+    fn shows_the_error_of_failing_queries() {
+        let failed = knex(
+            r#"{"sql":"insert into users (email) values (?)","bindings":["a@b.c"],"error":{"message":"duplicate key value violates unique constraint","code":"23505"}}"#,
+        );
+        let formatted = failed.format_compact();
+        assert!(formatted.msg.starts_with(
+            "SQL query failed: duplicate key value violates unique constraint (23505)\n"
+        ));
+        assert_eq!(formatted.color_overwrite, Some(Color::Red));
+        assert_eq!(failed.level(), Some(Level::Error));
+        assert_eq!(knex(r#"{"sql":"select 1"}"#).level(), None);
+    }
+
+    #[test]
+    fn reads_lines_with_unexpected_shapes() {
+        let both = knex(r#"{"sql":"select 1","error":"timeout","err":{"message":"other"}}"#);
+        assert_eq!(both.error_message().as_deref(), Some("timeout"));
+        let err = knex(r#"{"sql":"select 1","err":{"message":"timeout"}}"#);
+        assert_eq!(err.error_message().as_deref(), Some("timeout"));
+        let method = knex(r#"{"sql":"select 1","method":{"name":"select"}}"#);
+        assert!(!method
+            .format_detailed()
+            .extra
+            .unwrap()
+            .contains_key("method"));
     }
 }
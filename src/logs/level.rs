@@ -0,0 +1,124 @@
+use std::fmt;
+use std::str::FromStr;
+
+use termcolor::Color;
+
+/// Severity of a log. Values match the numeric levels used by pino and bunyan
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Level {
+    Trace = 10,
+    Debug = 20,
+    Info = 30,
+    Warn = 40,
+    Error = 50,
+    Fatal = 60,
+}
+
+impl Level {
+    /// Maps a numeric pino/bunyan level. Custom levels in between are rounded down to the closest
+    /// known one
+    pub fn from_number(level: u8) -> Level {
+        match level {
+            0..=19 => Level::Trace,
+            20..=29 => Level::Debug,
+            30..=39 => Level::Info,
+            40..=49 => Level::Warn,
+            50..=59 => Level::Error,
+            _ => Level::Fatal,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        }
+    }
+
+    /// Color used for the level badge
+    pub fn color(&self) -> Color {
+        match self {
+            Level::Trace => Color::White,
+            Level::Debug => Color::Blue,
+            Level::Info => Color::Green,
+            Level::Warn => Color::Yellow,
+            Level::Error => Color::Red,
+            Level::Fatal => Color::Magenta,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    /// Accepts level names (and a few common aliases) or pino numbers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse::<u8>() {
+            return Ok(Level::from_number(number));
+        }
+        match s.to_lowercase().as_str() {
+            "trace" | "trce" => Ok(Level::Trace),
+            "debug" | "dbug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" | "err" | "eror" => Ok(Level::Error),
+            "fatal" | "critical" | "crit" | "panic" | "dpanic" => Ok(Level::Fatal),
+            _ => Err(format!("Unknown level {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_pino_numbers() {
+        let levels: Vec<Level> = [10, 20, 30, 40, 50, 60]
+            .into_iter()
+            .map(Level::from_number)
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                Level::Trace,
+                Level::Debug,
+                Level::Info,
+                Level::Warn,
+                Level::Error,
+                Level::Fatal
+            ]
+        );
+        // Custom levels go to the closest known one below them
+        assert_eq!(Level::from_number(0), Level::Trace);
+        assert_eq!(Level::from_number(35), Level::Info);
+        assert_eq!(Level::from_number(59), Level::Error);
+        assert_eq!(Level::from_number(100), Level::Fatal);
+    }
+
+    #[test]
+    fn parses_names_aliases_and_numbers() {
+        let parse = |s: &str| s.parse::<Level>();
+        assert_eq!(parse("warn"), Ok(Level::Warn));
+        assert_eq!(parse("WARNING"), Ok(Level::Warn));
+        assert_eq!(parse("eror"), Ok(Level::Error));
+        assert_eq!(parse("err"), Ok(Level::Error));
+        assert_eq!(parse("dbug"), Ok(Level::Debug));
+        assert_eq!(parse("critical"), Ok(Level::Fatal));
+        assert_eq!(parse("dpanic"), Ok(Level::Fatal));
+        assert_eq!(parse("50"), Ok(Level::Error));
+        assert_eq!(parse("45"), Ok(Level::Warn));
+        assert_eq!(parse("verbose"), Err("Unknown level verbose".to_string()));
+        assert!(Level::Trace < Level::Fatal);
+    }
+}
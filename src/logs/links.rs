@@ -0,0 +1,154 @@
+use std::{
+    fs::File,
+    io::{self, IsTerminal},
+    process::Command,
+};
+
+use crate::logs::Location;
+
+/// Used when the config doesn't set a URL
+pub const DEFAULT_LINK_URL: &str = "file://{path}";
+
+/// How frames of stack traces point to the source code: the URL they link to and how paths in
+/// logs (like the ones inside a container) map to local ones
+#[derive(Debug, Clone)]
+pub struct Links {
+    /// `{path}`, `{line}` and `{column}` are replaced by the location of the frame
+    url: String,
+    /// Prefixes of paths as they're logged and what they're replaced with, longest first
+    paths: Vec<(String, String)>,
+}
+
+impl Default for Links {
+    fn default() -> Self {
+        Links::new(DEFAULT_LINK_URL.to_string(), Vec::new())
+    }
+}
+
+impl Links {
+    pub fn new(url: String, mut paths: Vec<(String, String)>) -> Links {
+        // The most specific prefix wins
+        paths.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+        Links { url, paths }
+    }
+
+    /// The location with its path mapped to a local one
+    pub fn local(&self, location: &Location) -> Location {
+        let path = self
+            .paths
+            .iter()
+            .find_map(|(from, to)| {
+                let rest = location.path.strip_prefix(from.as_str())?;
+                (rest.is_empty() || rest.starts_with('/') || from.ends_with('/'))
+                    .then(|| format!("{}{}", to, rest))
+            })
+            .unwrap_or_else(|| location.path.clone());
+        Location {
+            path,
+            ..location.clone()
+        }
+    }
+
+    pub fn url(&self, location: &Location) -> String {
+        let location = self.local(location);
+        self.url
+            .replace("{path}", &location.path)
+            .replace("{line}", &location.line.to_string())
+            .replace("{column}", &location.column.unwrap_or(1).to_string())
+    }
+}
+
+/// Opens a location in `$VISUAL` or `$EDITOR` (vi if neither is set) and waits for it to close
+pub fn open_in_editor(location: &Location) -> Result<(), String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let mut command = Command::new(program);
+    command.args(words).args(editor_args(program, location));
+    // When logs are piped to us stdin isn't the terminal, but terminal editors need it
+    if !io::stdin().is_terminal() {
+        if let Ok(tty) = File::open("/dev/tty") {
+            command.stdin(tty);
+        }
+    }
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+/// Arguments that make an editor open a file at a given line. Most terminal editors take
+/// `+line path`, GUI ones differ
+fn editor_args(program: &str, location: &Location) -> Vec<String> {
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let position = format!(
+        "{}:{}:{}",
+        location.path,
+        location.line,
+        location.column.unwrap_or(1)
+    );
+    match name {
+        "code" | "code-insiders" | "codium" | "cursor" => vec!["-g".to_string(), position],
+        "subl" | "zed" => vec![position],
+        "idea" | "webstorm" | "pycharm" => vec![
+            "--line".to_string(),
+            location.line.to_string(),
+            location.path.clone(),
+        ],
+        _ => vec![format!("+{}", location.line), location.path.clone()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(path: &str) -> Location {
+        Location {
+            path: path.to_string(),
+            line: 42,
+            column: Some(13),
+        }
+    }
+
+    #[test]
+    fn maps_paths_and_fills_the_url() {
+        let links = Links::new(
+            "vscode://file{path}:{line}:{column}".to_string(),
+            vec![
+                ("/app".to_string(), "/home/me/api".to_string()),
+                ("/app/packages/web".to_string(), "/home/me/web".to_string()),
+            ],
+        );
+        assert_eq!(
+            links.url(&location("/app/src/users.ts")),
+            "vscode://file/home/me/api/src/users.ts:42:13"
+        );
+        assert_eq!(
+            links.local(&location("/app/packages/web/index.ts")).path,
+            "/home/me/web/index.ts"
+        );
+        // Only whole directories are replaced
+        assert_eq!(links.local(&location("/apple/x.ts")).path, "/apple/x.ts");
+    }
+
+    #[test]
+    fn passes_the_line_the_way_each_editor_wants() {
+        let location = location("/app/src/users.ts");
+        assert_eq!(
+            editor_args("nvim", &location),
+            vec!["+42", "/app/src/users.ts"]
+        );
+        assert_eq!(
+            editor_args("/usr/bin/code", &location),
+            vec!["-g", "/app/src/users.ts:42:13"]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::logs::time::parse_timestamp;
+use crate::logs::FormattedLog;
+use crate::logs::Level;
+use crate::logs::ParsableLog;
+use crate::logs::{field_value, strip_line_ending};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use serde_json::{Map, Value};
+
+// Keys shown as the date, level and message. The first one found wins
+const TIME_KEYS: [&str; 2] = ["time", "ts"];
+const LEVEL_KEYS: [&str; 2] = ["level", "lvl"];
+const MSG_KEYS: [&str; 2] = ["msg", "message"];
+
+/// Logs written as `key=value` pairs, like `level=info msg="user created" id=42 admin`. Used by
+/// logrus, go-kit, log15 and Heroku among others
+#[derive(Debug)]
+pub struct LogfmtLog {
+    date: Option<DateTime<Local>>,
+    level: Option<Level>,
+    msg: Option<String>,
+    /// Pairs that aren't the date, level or message, in the order they were written
+    rest: Vec<(String, Value)>,
+    // Every pair, used by queries
+    fields: Value,
+}
+
+impl LogfmtLog {
+    /// Parses lines made of `key=value` pairs. Values can be quoted (with `\"` escapes) and keys
+    /// without a value count as `true`. To avoid mistaking plain text for logfmt, the line needs
+    /// a known key with a value (like `level=info` or `msg=hi`), or has to be made of pairs only
+    /// (at least two of them, like `at=info status=200`)
+    pub fn from_line(line: &str) -> Option<Self> {
+        let pairs = parse_pairs(strip_line_ending(line))?;
+        let is_known = |key: &str| {
+            TIME_KEYS
+                .iter()
+                .chain(&LEVEL_KEYS)
+                .chain(&MSG_KEYS)
+                .any(|k| *k == key)
+        };
+        let with_value = pairs.iter().filter(|(_, v)| v.is_some()).count();
+        let has_known = pairs.iter().any(|(k, v)| v.is_some() && is_known(k));
+        // Plain text often has a few pairs in it, like `Listening host=0.0.0.0 port=3000`
+        let only_pairs = with_value >= 2 && with_value == pairs.len();
+        if !has_known && !only_pairs {
+            return None;
+        }
+
+        let find = |keys: &[&str]| {
+            keys.iter().find_map(|key| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .and_then(|(_, v)| v.clone())
+            })
+        };
+        let date = find(&TIME_KEYS).and_then(|t| parse_timestamp(&t, None));
+        let level = find(&LEVEL_KEYS).and_then(|l| l.parse().ok());
+        let msg = find(&MSG_KEYS);
+
+        let mut fields = Map::new();
+        let mut rest = Vec::new();
+        for (key, value) in pairs {
+            let value = match value {
+                Some(value) => field_value(&value),
+                None => Value::Bool(true),
+            };
+            if !is_known(&key) {
+                rest.push((key.clone(), value.clone()));
+            }
+            fields.insert(key, value);
+        }
+
+        Some(LogfmtLog {
+            date,
+            level,
+            msg,
+            rest,
+            fields: Value::Object(fields),
+        })
+    }
+
+    /// Pairs that aren't shown anywhere else, written back as logfmt
+    fn rest_as_text(&self) -> String {
+        let pairs: Vec<String> = self
+            .rest
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(s) if s.is_empty() || s.contains([' ', '"', '=']) => {
+                    format!("{}={:?}", key, s)
+                }
+                Value::String(s) => format!("{}={}", key, s),
+                Value::Bool(true) => key.clone(),
+                value => format!("{}={}", key, value),
+            })
+            .collect();
+        pairs.join(" ")
+    }
+}
+
+impl ParsableLog for LogfmtLog {
+    fn format_compact(&self) -> FormattedLog {
+        FormattedLog {
+            date: self.date,
+            level: self.level,
+            // Without a message the pairs are the message
+            msg: self.msg.clone().unwrap_or_else(|| self.rest_as_text()),
+            msg_colors: Vec::new(),
+            extra: None,
+            color_overwrite: None,
+            stack: None,
+        }
+    }
+
+    fn format_detailed(&self) -> FormattedLog {
+        let extra: HashMap<String, Value> = self.rest.iter().cloned().collect();
+        FormattedLog {
+            // When they're already the message there's no need to show the pairs again
+            extra: if extra.is_empty() || self.msg.is_none() {
+                None
+            } else {
+                Some(extra)
+            },
+            ..self.format_compact()
+        }
+    }
+
+    fn level(&self) -> Option<Level> {
+        self.level
+    }
+
+    fn fields(&self) -> &Value {
+        &self.fields
+    }
+}
+
+/// Splits a line into its pairs. Keys without `=` have no value. Returns nothing if the line
+/// can't be logfmt (like an unterminated quote or a key starting with a quote)
+fn parse_pairs(line: &str) -> Option<Vec<(String, Option<String>)>> {
+    let mut pairs = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(pairs);
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            if c == '"' {
+                return None;
+            }
+            key.push(c);
+        }
+        if key.is_empty() {
+            // A pair without a key, like ` =value`
+            return None;
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            pairs.push((key, None));
+            continue;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        'r' => value.push('\r'),
+                        c @ ('"' | '\\') => value.push(c),
+                        c => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        pairs.push((key, Some(value)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_known_keys_and_keeps_the_rest() {
+        let log = LogfmtLog::from_line(
+            "time=2024-01-31T10:00:00Z lvl=warn msg=\"user \\\"bob\\\" created\" id=42 admin\n",
+        )
+        .unwrap();
+        assert_eq!(log.level, Some(Level::Warn));
+        assert_eq!(log.msg.as_deref(), Some("user \"bob\" created"));
+        assert!(log.date.is_some());
+        assert_eq!(log.fields["id"], 42);
+        assert_eq!(log.fields["admin"], true);
+        let extra = log.format_detailed().extra.unwrap();
+        assert_eq!(extra.len(), 2);
+    }
+
+    #[test]
+    fn shows_pairs_when_theres_no_message() {
+        let log = LogfmtLog::from_line("at=info method=GET path=/users status=200").unwrap();
+        assert_eq!(log.level, None);
+        assert_eq!(
+            log.format_compact().msg,
+            "at=info method=GET path=/users status=200"
+        );
+    }
+
+    #[test]
+    fn rejects_plain_text_and_json() {
+        assert!(LogfmtLog::from_line("Server started on port 3000").is_none());
+        assert!(LogfmtLog::from_line("Error: x=5 happened").is_none());
+        assert!(LogfmtLog::from_line("Listening host=0.0.0.0 port=3000").is_none());
+        assert!(LogfmtLog::from_line("level up now").is_none());
+        assert!(LogfmtLog::from_line(r#"{"level":30,"msg":"hi"}"#).is_none());
+        assert!(LogfmtLog::from_line("msg=\"never closed").is_none());
+    }
+}
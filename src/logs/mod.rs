@@ -1,67 +1,268 @@
 use chrono::{DateTime, Local};
 use colored_json::ToColoredJson;
+use regex::Regex;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use std::io::Write;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+mod bindings;
+mod custom;
+mod detect;
+mod json;
 mod knex;
+mod level;
+mod links;
+mod logfmt;
+mod registry;
+mod sql;
+mod stack;
 mod standard;
+mod time;
+mod warning;
+
+pub use custom::CustomFormat;
+pub use detect::{Detection, Detector};
+pub use json::JsonKeys;
+pub use level::Level;
+pub use links::{open_in_editor, Links, DEFAULT_LINK_URL};
+pub use registry::ParserRegistry;
+pub use sql::fingerprint;
+pub use stack::{error_headline, LineAssembler, LineKind, Location, StackTrace, FLUSH_AFTER};
+pub use warning::Warning;
+
+/// How dates are shown next to every log
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S:";
+
+// Turned off by `--no-color` or the NO_COLOR environment variable
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables colors for everything printed from now on
+pub fn set_color_enabled(enabled: bool) {
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn color_enabled() -> bool {
+    COLOR_ENABLED.load(Ordering::Relaxed)
+}
+
+fn color_choice() -> ColorChoice {
+    if color_enabled() {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    }
+}
 
 /// A compact log object that can be pretty printed
 pub struct FormattedLog {
-    pub date: DateTime<Local>,
+    /// When the log happened. Formats without a time leave it empty and the time the line arrived
+    /// is used instead
+    pub date: Option<DateTime<Local>>,
+    /// Shown as a colored badge next to the date when known
+    pub level: Option<Level>,
     pub msg: String,
+    /// Parts of the message shown in their own color (like SQL syntax), by byte range
+    pub msg_colors: Vec<(Range<usize>, Color)>,
     pub extra: Option<HashMap<String, Value>>,
     pub color_overwrite: Option<Color>,
+    /// Printed under the message, one frame per line
+    pub stack: Option<StackTrace>,
+}
+
+/// Settings that change how a log is printed but don't depend on the log itself
+#[derive(Default)]
+pub struct PrintOptions<'a> {
+    /// Printed first, it's used to tell apart logs from different processes
+    pub label: Option<(&'a str, Color)>,
+    /// Matches of this in the message are highlighted
+    pub highlight: Option<&'a Regex>,
+    /// How the date is shown, `DATE_FORMAT` when not set
+    pub date_format: Option<&'a str>,
+    /// When set frames of stack traces are links to the source code
+    pub links: Option<&'a Links>,
+    /// Shows the frames of the runtime itself instead of folding them
+    pub all_frames: bool,
 }
 
 impl FormattedLog {
     /// Prints log to stdout
     /// It will properly apply default colors or overwrite with optional color parameter
     /// It also pretty prints json objects if possible
-    pub fn print(&self) {
-        let formatted_date = self.date.format("%Y-%m-%d %H:%M:%S:");
-
-        print_color(&formatted_date.to_string(), Color::Cyan);
-        print!(" ");
-        print_color(
-            &self.msg.trim(),
-            self.color_overwrite.unwrap_or(Color::White),
-        );
+    pub fn print(&self, options: &PrintOptions) {
+        if let Some((label, color)) = options.label {
+            print_color(label, color);
+            print!(" ");
+        }
+
+        if let Some(date) = self.date {
+            let format = options.date_format.unwrap_or(DATE_FORMAT);
+            print_color(&date.format(format).to_string(), Color::Cyan);
+            print!(" ");
+        }
+        if let Some(level) = self.level {
+            print_color(
+                &format!("{:<5}", level.name().to_uppercase()),
+                level.color(),
+            );
+            print!(" ");
+        }
+        let color = self.color_overwrite.unwrap_or(Color::White);
+        if self.msg_colors.is_empty() {
+            print_highlighted(self.msg.trim(), color, options.highlight);
+        } else {
+            // Ranges point into the untrimmed message
+            let start = self.msg.len() - self.msg.trim_start().len();
+            let msg = self.msg.trim();
+            let colors: Vec<(Range<usize>, Color)> = self
+                .msg_colors
+                .iter()
+                .filter(|(range, _)| range.start >= start && range.end <= start + msg.len())
+                .map(|(range, color)| (range.start - start..range.end - start, *color))
+                .collect();
+            print_colored(msg, color, &colors, options.highlight);
+        }
+
+        if let Some(stack) = &self.stack {
+            for (kind, line) in stack.shown(options.all_frames) {
+                println!();
+                // Terminals that support OSC 8 make the frame clickable, others ignore it
+                let link = options
+                    .links
+                    .filter(|_| color_enabled())
+                    .zip(Location::of_frame(&line))
+                    .map(|(links, location)| links.url(&location));
+                if let Some(url) = &link {
+                    print!("\x1b]8;;{}\x1b\\", url);
+                }
+                match kind {
+                    LineKind::Error => print_highlighted(&line, Color::Red, options.highlight),
+                    LineKind::Dependency | LineKind::Folded => print_dimmed(&line, color),
+                    LineKind::Frame | LineKind::Other => {
+                        print_highlighted(&line, color, options.highlight)
+                    }
+                }
+                if link.is_some() {
+                    print!("\x1b]8;;\x1b\\");
+                }
+            }
+        }
 
         if let Some(extra) = &self.extra {
-            print!("\n");
+            println!();
             print_color(
-                &serde_json::to_string_pretty(&extra)
+                serde_json::to_string_pretty(&extra)
                     .unwrap_or("".to_string())
                     .trim(),
                 Color::Cyan,
             );
         }
 
-        print!("\n");
+        println!();
     }
 }
 
 /// Describes a log that can be parsed (so a known log type) and can easily be printed
-pub trait ParsableLog {
+/// Parsed logs are kept in history and shared between threads
+pub trait ParsableLog: fmt::Debug + Send + Sync {
     fn format_compact(&self) -> FormattedLog;
     fn format_detailed(&self) -> FormattedLog;
-    fn from_line(line: &str) -> Option<Self>
-    where
-        Self: Sized;
+    /// Severity of the log if the format has one. Used to filter logs below a threshold
+    fn level(&self) -> Option<Level> {
+        None
+    }
+    /// Every field of the log as JSON, used to evaluate queries
+    fn fields(&self) -> &Value;
+    /// The database query the log is about, if it's one. Used to aggregate query statistics
+    fn query(&self) -> Option<QueryEvent<'_>> {
+        None
+    }
+    /// The HTTP request the log is about, if it's one. Used to tell which queries ran for it
+    fn request(&self) -> Option<RequestInfo> {
+        None
+    }
+    /// Roughly how many bytes the log takes in memory, used to limit history. Parsers keep the
+    /// properties they show (message, data, query...) next to the fields, so the fields are
+    /// counted twice
+    fn size(&self) -> usize {
+        2 * value_size(self.fields())
+    }
+}
+
+/// Bytes taken by a JSON value: its own size plus the strings, arrays and objects it holds
+fn value_size(value: &Value) -> usize {
+    let own = std::mem::size_of::<Value>();
+    match value {
+        Value::String(s) => own + s.len(),
+        Value::Array(values) => own + values.iter().map(value_size).sum::<usize>(),
+        Value::Object(object) => {
+            own + object
+                .iter()
+                .map(|(key, value)| std::mem::size_of::<String>() + key.len() + value_size(value))
+                .sum::<usize>()
+        }
+        _ => own,
+    }
+}
+
+/// A database query as it was logged
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryEvent<'a> {
+    pub sql: &'a str,
+    /// Identifies the query across the lines logged when it starts and when it gets a response
+    pub uid: Option<&'a str>,
+    /// The HTTP request the query ran for, when it was logged with one
+    pub request_id: Option<&'a str>,
+    /// How long it took in milliseconds, when the log says
+    pub duration: Option<f64>,
+}
+
+// Properties where request ids are usually kept, in order
+const REQUEST_ID_KEYS: [&str; 5] = [
+    "requestId",
+    "reqId",
+    "request_id",
+    "correlationId",
+    "traceId",
+];
+
+/// An HTTP request as it was logged
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestInfo {
+    /// Tells apart requests to the same endpoint, when the log has one
+    pub id: Option<String>,
+    pub method: Option<String>,
+    pub url: Option<String>,
+    /// Whether the log is about the response (like pino-http's `request completed`), which
+    /// comes after the queries of the request
+    pub completed: bool,
+}
+
+impl RequestInfo {
+    /// Like `GET /users?page=2`
+    pub fn endpoint(&self) -> String {
+        match (&self.method, &self.url) {
+            (Some(method), Some(url)) => format!("{} {}", method, url),
+            (None, Some(url)) => url.clone(),
+            (Some(method), None) => method.clone(),
+            (None, None) => "unknown endpoint".to_string(),
+        }
+    }
 }
 
 /// Internal function used to print known log types (like compact log and others)
 /// It calls reset on stdout which should undo any coloring changes, but the lib
 /// used for this doesn't seem to always work that way
 fn print_color(text: &str, color: Color) {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    let mut to_write = text.clone().to_string();
+    let mut stdout = StandardStream::stdout(color_choice());
+    let mut to_write = text.to_string();
     let mut color_to_write = Some(color);
     if let Ok(parsed) = serde_json::from_str::<Value>(text) {
-        if parsed.is_object() || parsed.is_array() {
+        if color_enabled() && (parsed.is_object() || parsed.is_array()) {
             if let Ok(colored) = to_write.to_colored_json_auto() {
                 to_write = colored;
                 // Ignore color to write in favor of colored_json_auto
@@ -83,12 +284,188 @@ fn print_color(text: &str, color: Color) {
     }
 }
 
-pub fn try_parse_known_log(line: &str) -> Option<Box<dyn ParsableLog>> {
-    if let Some(v) = standard::StandardLog::from_line(line) {
-        Some(Box::new(v))
-    } else if let Some(v) = knex::KnexLog::from_line(line) {
-        Some(Box::new(v))
-    } else {
-        None
+/// Prints text in the given color, with every match of the highlight pattern standing out
+fn print_highlighted(text: &str, color: Color, highlight: Option<&Regex>) {
+    let Some(highlight) = highlight.filter(|h| h.is_match(text)) else {
+        print_color(text, color);
+        return;
+    };
+    let mut stdout = StandardStream::stdout(color_choice());
+    let mut last = 0;
+    for m in highlight.find_iter(text) {
+        write_segment(
+            &mut stdout,
+            &text[last..m.start()],
+            ColorSpec::new().set_fg(Some(color)),
+        );
+        write_segment(
+            &mut stdout,
+            m.as_str(),
+            ColorSpec::new()
+                .set_fg(Some(Color::Black))
+                .set_bg(Some(Color::Yellow)),
+        );
+        last = m.end();
+    }
+    write_segment(
+        &mut stdout,
+        &text[last..],
+        ColorSpec::new().set_fg(Some(color)),
+    );
+}
+
+/// Prints text in `color` except for the ranges that have their own. Highlights win over both
+fn print_colored(
+    text: &str,
+    color: Color,
+    colors: &[(Range<usize>, Color)],
+    highlight: Option<&Regex>,
+) {
+    let matches: Vec<Range<usize>> = highlight
+        .map(|h| h.find_iter(text).map(|m| m.range()).collect())
+        .unwrap_or_default();
+    let mut bounds: Vec<usize> = colors
+        .iter()
+        .map(|(range, _)| range)
+        .chain(&matches)
+        .flat_map(|range| [range.start, range.end])
+        .chain([0, text.len()])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut stdout = StandardStream::stdout(color_choice());
+    for segment in bounds.windows(2) {
+        let (from, to) = (segment[0], segment[1]);
+        let mut spec = ColorSpec::new();
+        if matches.iter().any(|m| m.start <= from && to <= m.end) {
+            spec.set_fg(Some(Color::Black)).set_bg(Some(Color::Yellow));
+        } else {
+            let fg = colors
+                .iter()
+                .find(|(range, _)| range.start <= from && to <= range.end)
+                .map_or(color, |(_, color)| *color);
+            spec.set_fg(Some(fg));
+        }
+        write_segment(&mut stdout, &text[from..to], &spec);
+    }
+}
+
+/// Prints text that isn't as important as the rest, like frames of dependencies
+fn print_dimmed(text: &str, color: Color) {
+    let mut stdout = StandardStream::stdout(color_choice());
+    write_segment(
+        &mut stdout,
+        text,
+        ColorSpec::new().set_fg(Some(color)).set_dimmed(true),
+    );
+}
+
+fn write_segment(stdout: &mut StandardStream, text: &str, spec: &ColorSpec) {
+    if text.is_empty() {
+        return;
+    }
+    stdout.set_color(spec).expect("Failed to set color");
+    if let Err(e) = write!(stdout, "{}", text) {
+        println!("Failed to write to stream {}", e);
+    }
+    if let Err(e) = stdout.reset() {
+        println!("Failed to reset stream {}", e);
+    }
+}
+
+/// Text values (captured by a regex, read from logfmt...) are kept as numbers when they are, so
+/// queries like `status>=500` work
+fn field_value(value: &str) -> Value {
+    match value.parse::<i64>() {
+        Ok(number) if number.to_string() == value => Value::from(number),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+fn strip_line_ending(line: &str) -> &str {
+    line.trim_end_matches(['\r', '\n'])
+}
+
+/// One of the log formats we know how to parse
+#[derive(Debug, Clone)]
+pub enum Parser {
+    Standard,
+    Knex,
+    Logfmt,
+    /// JSON with a string level, like winston's. Key names can be changed in the config
+    Json(Arc<JsonKeys>),
+    /// Declared in the config
+    Custom(Arc<CustomFormat>),
+}
+
+impl Parser {
+    /// Every built in parser
+    pub fn built_in(json_keys: JsonKeys) -> [Parser; 4] {
+        [
+            Parser::Standard,
+            Parser::Knex,
+            Parser::Logfmt,
+            Parser::Json(Arc::new(json_keys)),
+        ]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Parser::Standard => "standard",
+            Parser::Knex => "knex",
+            Parser::Logfmt => "logfmt",
+            Parser::Json(_) => "json",
+            Parser::Custom(format) => &format.name,
+        }
+    }
+
+    /// Where the parser goes in the registry when nothing else is configured. Parsers that accept
+    /// almost any JSON (like standard, which only needs an integer `level`) go last
+    pub fn default_priority(&self) -> i32 {
+        match self {
+            Parser::Knex => 30,
+            Parser::Custom(_) => 20,
+            Parser::Logfmt => 15,
+            Parser::Standard => 10,
+            Parser::Json(_) => 5,
+        }
+    }
+
+    /// A quick look at whether the line is meant for this parser, even when another one was
+    /// detected for the stream. Only specific formats that catch-all parsers would also accept
+    /// (like knex queries, which are JSON with a numeric `level` sometimes) recognize lines
+    pub fn recognizes(&self, line: &str) -> bool {
+        match self {
+            Parser::Knex => line.contains("\"sql\""),
+            _ => false,
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Option<Arc<dyn ParsableLog>> {
+        match self {
+            Parser::Standard => {
+                standard::StandardLog::from_line(line).map(|v| Arc::new(v) as Arc<dyn ParsableLog>)
+            }
+            Parser::Knex => {
+                knex::KnexLog::from_line(line).map(|v| Arc::new(v) as Arc<dyn ParsableLog>)
+            }
+            Parser::Logfmt => {
+                logfmt::LogfmtLog::from_line(line).map(|v| Arc::new(v) as Arc<dyn ParsableLog>)
+            }
+            Parser::Json(keys) => {
+                json::JsonLog::from_line(line, keys).map(|v| Arc::new(v) as Arc<dyn ParsableLog>)
+            }
+            Parser::Custom(format) => format
+                .parse(line)
+                .map(|v| Arc::new(v) as Arc<dyn ParsableLog>),
+        }
+    }
+}
+
+/// Parsers are told apart by name, custom formats can't reuse the name of a built in one
+impl PartialEq for Parser {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
     }
 }
@@ -0,0 +1,280 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::logs::{ParsableLog, Parser};
+
+/// A parser together with its settings and how many lines it matched
+#[derive(Debug)]
+struct Entry {
+    parser: Parser,
+    priority: i32,
+    enabled: AtomicBool,
+    matched: AtomicUsize,
+}
+
+/// How a parser is doing, as shown by `c:stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParserStats {
+    pub name: String,
+    pub priority: i32,
+    pub enabled: bool,
+    pub matched: usize,
+}
+
+/// Every parser that can be tried on a line. Parsers with a higher priority are tried first (ties
+/// keep the order they were registered in) and the first one that parses the line wins, so more
+/// specific formats should have a higher priority than catch-all ones.
+///
+/// The registry is shared by every reader thread, parsers are turned on and off and lines are
+/// counted without locking
+#[derive(Debug, Default)]
+pub struct ParserRegistry {
+    entries: Vec<Entry>,
+    unmatched: AtomicUsize,
+}
+
+impl ParserRegistry {
+    pub fn new() -> Self {
+        ParserRegistry::default()
+    }
+
+    /// Adds a parser, replacing the one with the same name if there's one
+    pub fn register(&mut self, parser: Parser, priority: i32, enabled: bool) {
+        self.entries.retain(|e| e.parser != parser);
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.priority < priority)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(
+            index,
+            Entry {
+                parser,
+                priority,
+                enabled: AtomicBool::new(enabled),
+                matched: AtomicUsize::new(0),
+            },
+        );
+    }
+
+    /// Turns a parser on or off. Lines that were already parsed keep their format
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), String> {
+        let index = self.index_of(name)?;
+        self.entries[index]
+            .enabled
+            .store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Tries every enabled parser in order, returning the first log that could be parsed
+    pub fn parse(&self, line: &str) -> Option<Arc<dyn ParsableLog>> {
+        self.parse_in(line, self.order(line, None, false))
+    }
+
+    /// Like `parse`, but the parser at `preferred` (usually the detected format of the stream) is
+    /// tried first. The only parsers tried before it are the more specific ones that recognize
+    /// the line as theirs (like knex with a `sql` property, which standard would parse too)
+    pub fn parse_preferring(
+        &self,
+        line: &str,
+        preferred: Option<usize>,
+    ) -> Option<Arc<dyn ParsableLog>> {
+        self.parse_in(line, self.order(line, preferred, true))
+    }
+
+    /// Like `parse`, but the parser at `first` (set with `--parser`) is tried before every other
+    pub fn parse_forcing(&self, line: &str, first: Option<usize>) -> Option<Arc<dyn ParsableLog>> {
+        self.parse_in(line, self.order(line, first, false))
+    }
+
+    /// Indexes of the enabled parsers in the order they're tried, starting with `first`. With
+    /// `specific_first`, parsers with a higher priority that recognize the line go before it
+    fn order(&self, line: &str, first: Option<usize>, specific_first: bool) -> Vec<usize> {
+        let enabled = |i: &usize| self.entries[*i].enabled.load(Ordering::Relaxed);
+        let Some(first) = first.filter(|i| *i < self.entries.len()) else {
+            return (0..self.entries.len()).filter(enabled).collect();
+        };
+        let mut order: Vec<usize> = if specific_first {
+            (0..first)
+                .filter(|i| self.entries[*i].parser.recognizes(line))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        order.push(first);
+        let rest: Vec<usize> = (0..self.entries.len())
+            .filter(|i| !order.contains(i))
+            .collect();
+        order.extend(rest);
+        order.retain(enabled);
+        order
+    }
+
+    fn parse_in(&self, line: &str, order: Vec<usize>) -> Option<Arc<dyn ParsableLog>> {
+        let parsed = order.into_iter().find_map(|i| {
+            let parsed = self.entries[i].parser.parse(line)?;
+            self.entries[i].matched.fetch_add(1, Ordering::Relaxed);
+            Some(parsed)
+        });
+        if parsed.is_none() {
+            self.unmatched.fetch_add(1, Ordering::Relaxed);
+        }
+        parsed
+    }
+
+    /// Parses the line with every enabled parser, adding one to the score of the ones that
+    /// understand it (scores are indexed like the registry). Returns the parsed lines, for
+    /// `pick` to choose from once the scores settled on a parser
+    pub fn score(&self, line: &str, scores: &mut [usize]) -> Vec<Option<Arc<dyn ParsableLog>>> {
+        self.entries
+            .iter()
+            .zip(scores.iter_mut())
+            .map(|(entry, score)| {
+                if !entry.enabled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let parsed = entry.parser.parse(line);
+                if parsed.is_some() {
+                    *score += 1;
+                }
+                parsed
+            })
+            .collect()
+    }
+
+    /// The log `parse_preferring` would return, taken from lines already parsed by `score`
+    pub fn pick(
+        &self,
+        line: &str,
+        mut parsed: Vec<Option<Arc<dyn ParsableLog>>>,
+        preferred: Option<usize>,
+    ) -> Option<Arc<dyn ParsableLog>> {
+        let picked = self
+            .order(line, preferred, true)
+            .into_iter()
+            .find_map(|i| Some((i, parsed.get_mut(i)?.take()?)));
+        match picked {
+            Some((i, log)) => {
+                self.entries[i].matched.fetch_add(1, Ordering::Relaxed);
+                Some(log)
+            }
+            None => {
+                self.unmatched.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Position of a parser, which is also the order it's tried in
+    pub fn index_of(&self, name: &str) -> Result<usize, String> {
+        self.entries
+            .iter()
+            .position(|e| e.parser.name() == name)
+            .ok_or_else(|| format!("Unknown parser {}, expected one of {}", name, self.names()))
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|e| e.parser.name())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Every parser in the order they're tried
+    pub fn stats(&self) -> Vec<ParserStats> {
+        self.entries
+            .iter()
+            .map(|e| ParserStats {
+                name: e.parser.name().to_string(),
+                priority: e.priority,
+                enabled: e.enabled.load(Ordering::Relaxed),
+                matched: e.matched.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// How many lines no parser could parse
+    pub fn unmatched(&self) -> usize {
+        self.unmatched.load(Ordering::Relaxed)
+    }
+
+    fn names(&self) -> String {
+        let names: Vec<_> = self.entries.iter().map(|e| e.parser.name()).collect();
+        names.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::JsonKeys;
+
+    const STANDARD_LINE: &str = r#"{"level":30,"msg":"hi"}"#;
+    const KNEX_LINE: &str = r#"{"sql":"select 1","bindings":[]}"#;
+
+    fn names(registry: &ParserRegistry) -> Vec<String> {
+        registry.stats().into_iter().map(|s| s.name).collect()
+    }
+
+    #[test]
+    fn tries_higher_priorities_first() {
+        let mut registry = ParserRegistry::new();
+        registry.register(Parser::Standard, 10, true);
+        registry.register(Parser::Knex, 30, true);
+        assert_eq!(names(&registry), vec!["knex", "standard"]);
+    }
+
+    #[test]
+    fn counts_matches_and_unmatched_lines() {
+        let mut registry = ParserRegistry::new();
+        registry.register(Parser::Knex, 30, true);
+        registry.register(Parser::Standard, 10, true);
+        for line in [STANDARD_LINE, STANDARD_LINE, KNEX_LINE, "plain text"] {
+            registry.parse(line);
+        }
+        let matched: Vec<_> = registry.stats().iter().map(|s| s.matched).collect();
+        assert_eq!(matched, vec![1, 2]);
+        assert_eq!(registry.unmatched(), 1);
+    }
+
+    #[test]
+    fn disabled_parsers_are_skipped() {
+        let mut registry = ParserRegistry::new();
+        registry.register(Parser::Standard, 10, true);
+        registry.set_enabled("standard", false).unwrap();
+        assert!(registry.parse(STANDARD_LINE).is_none());
+        registry.set_enabled("standard", true).unwrap();
+        assert!(registry.parse(STANDARD_LINE).is_some());
+        assert!(registry.set_enabled("nope", false).is_err());
+    }
+
+    #[test]
+    fn tries_the_preferred_parser_first() {
+        let keys = JsonKeys {
+            level: vec!["severity".to_string()],
+            ..JsonKeys::default()
+        };
+        let mut registry = ParserRegistry::new();
+        registry.register(Parser::Knex, 30, true);
+        registry.register(Parser::Standard, 10, true);
+        registry.register(Parser::Json(Arc::new(keys)), 5, true);
+        let matched = |registry: &ParserRegistry| -> Vec<usize> {
+            registry.stats().iter().map(|s| s.matched).collect()
+        };
+        // Understood by standard and json, json wins when it's preferred
+        let both = r#"{"level":30,"severity":"info","msg":"hi"}"#;
+        registry.parse(both);
+        registry.parse_preferring(both, Some(2));
+        assert_eq!(matched(&registry), vec![0, 1, 1]);
+        // Except over knex when the line is a query
+        let query = r#"{"level":30,"sql":"select 1"}"#;
+        registry.parse_preferring(query, Some(1));
+        assert_eq!(matched(&registry), vec![1, 1, 1]);
+        // Unless it was set with `--parser`
+        registry.parse_forcing(query, Some(1));
+        assert_eq!(matched(&registry), vec![1, 2, 1]);
+    }
+}
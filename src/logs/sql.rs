@@ -0,0 +1,257 @@
+use std::ops::Range;
+
+use termcolor::Color;
+
+// Words colored as keywords. Matched without case
+#[rustfmt::skip]
+const KEYWORDS: [&str; 96] = [
+    "add", "all", "alter", "and", "any", "as", "asc", "begin", "between", "by", "case", "cast",
+    "check", "column", "commit", "conflict", "constraint", "create", "cross", "default", "delete",
+    "desc", "distinct", "do", "drop", "else", "end", "except", "exists", "false", "fetch", "for",
+    "foreign", "from", "full", "group", "having", "if", "ilike", "in", "index", "inner", "insert",
+    "intersect", "into", "is", "join", "key", "lateral", "left", "like", "limit", "lock", "not",
+    "nothing", "null", "offset", "on", "or", "order", "outer", "over", "partition", "primary",
+    "references", "release", "returning", "right", "rollback", "savepoint", "select", "set",
+    "share", "table", "then", "to", "top", "transaction", "true", "truncate", "union", "unique",
+    "update", "upsert", "using", "values", "view", "when", "where", "window", "with", "count",
+    "sum", "avg", "min", "max",
+];
+
+/// What a piece of SQL is, which decides its color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Keyword,
+    Identifier,
+    /// Between double quotes, backticks or brackets
+    QuotedIdentifier,
+    String,
+    Number,
+    /// `?`, `$1`, `:name`... waiting for a binding
+    Placeholder,
+    Comment,
+    /// Operators and punctuation
+    Other,
+}
+
+/// Splits SQL in tokens, skipping whitespace. Never fails: anything it doesn't know about is a
+/// one character `Other` token, unterminated strings and comments go until the end
+pub fn tokenize(sql: &str) -> Vec<(TokenKind, Range<usize>)> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    // Index of the first byte after `from` that doesn't pass the test
+    let skip_while = |from: usize, test: &dyn Fn(u8) -> bool| {
+        (from..bytes.len())
+            .find(|j| !test(bytes[*j]))
+            .unwrap_or(bytes.len())
+    };
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii();
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        let (kind, end) = match b {
+            b if b.is_ascii_whitespace() => {
+                i = skip_while(i, &|b| b.is_ascii_whitespace());
+                continue;
+            }
+            b'-' if next == Some(b'-') => (TokenKind::Comment, skip_while(i, &|b| b != b'\n')),
+            b'/' if next == Some(b'*') => (
+                TokenKind::Comment,
+                sql[i + 2..]
+                    .find("*/")
+                    .map(|j| i + 2 + j + 2)
+                    .unwrap_or(bytes.len()),
+            ),
+            b'\'' => (TokenKind::String, closing(bytes, i, b'\'')),
+            b'"' | b'`' => (TokenKind::QuotedIdentifier, closing(bytes, i, b)),
+            b'[' => (TokenKind::QuotedIdentifier, closing(bytes, i, b']')),
+            b'0'..=b'9' => (
+                TokenKind::Number,
+                skip_while(i, &|b| b.is_ascii_alphanumeric() || b == b'.'),
+            ),
+            b'?' => (
+                TokenKind::Placeholder,
+                if next == Some(b'?') { i + 2 } else { i + 1 },
+            ),
+            b'$' | b'@' | b':' if next.is_some_and(is_word) && next != Some(b':') => {
+                (TokenKind::Placeholder, skip_while(i + 1, &is_word))
+            }
+            b':' if next == Some(b':') => (TokenKind::Other, i + 2),
+            b if is_word(b) => {
+                let end = skip_while(i, &is_word);
+                let word = sql[i..end].to_lowercase();
+                if KEYWORDS.contains(&word.as_str()) {
+                    (TokenKind::Keyword, end)
+                } else {
+                    (TokenKind::Identifier, end)
+                }
+            }
+            _ => (TokenKind::Other, i + 1),
+        };
+        // Multi byte characters are words, so this is always a char boundary
+        tokens.push((kind, i..end));
+        i = end;
+    }
+    tokens
+}
+
+/// Colors of the parts of a query worth coloring. `bindings` are ranges of values that were put
+/// in place of placeholders, they're colored like placeholders no matter what they are
+pub fn highlight(sql: &str, bindings: &[Range<usize>]) -> Vec<(Range<usize>, Color)> {
+    let mut colors: Vec<(Range<usize>, Color)> = tokenize(sql)
+        .into_iter()
+        .filter(|(_, range)| {
+            !bindings
+                .iter()
+                .any(|b| range.start < b.end && b.start < range.end)
+        })
+        .filter_map(|(kind, range)| {
+            let color = match kind {
+                TokenKind::Keyword => Color::Blue,
+                TokenKind::QuotedIdentifier => Color::Cyan,
+                TokenKind::String => Color::Green,
+                TokenKind::Number => Color::Magenta,
+                TokenKind::Placeholder => Color::Red,
+                TokenKind::Comment => Color::Ansi256(244),
+                TokenKind::Identifier | TokenKind::Other => return None,
+            };
+            Some((range, color))
+        })
+        .chain(bindings.iter().map(|b| (b.clone(), Color::Red)))
+        .collect();
+    colors.sort_by_key(|(range, _)| range.start);
+    colors
+}
+
+/// The query with its literals and placeholders replaced by `?`, so queries that only differ in
+/// their values are the same. Keywords are lowercased, comments dropped, whitespace collapsed and
+/// lists of values (`in (?, ?, ?)`, rows of an insert) shortened to one
+pub fn fingerprint(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut last_end = None;
+    for (kind, range) in tokenize(sql) {
+        if kind == TokenKind::Comment {
+            continue;
+        }
+        if last_end.is_some_and(|end| end < range.start) {
+            out.push(' ');
+        }
+        last_end = Some(range.end);
+        match kind {
+            TokenKind::Keyword => out.push_str(&sql[range].to_lowercase()),
+            TokenKind::String | TokenKind::Number | TokenKind::Placeholder => out.push('?'),
+            _ => out.push_str(&sql[range]),
+        }
+    }
+    for (list, single) in [
+        ("?, ?", "?"),
+        ("?,?", "?"),
+        ("(?), (?)", "(?)"),
+        ("(?),(?)", "(?)"),
+    ] {
+        while out.contains(list) {
+            out = out.replace(list, single);
+        }
+    }
+    out.trim_end_matches(';').trim().to_string()
+}
+
+/// Index right after the quote closing the one at `start`. Doubled quotes are escaped quotes
+fn closing(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(sql)
+            .into_iter()
+            .map(|(kind, range)| (kind, &sql[range]))
+            .collect()
+    }
+
+    #[test]
+    fn tokenizes_queries() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds(r#"SELECT "id", name::text FROM users WHERE x = 'it''s' AND y > 1.5 -- why"#),
+            vec![
+                (Keyword, "SELECT"),
+                (QuotedIdentifier, "\"id\""),
+                (Other, ","),
+                (Identifier, "name"),
+                (Other, "::"),
+                (Identifier, "text"),
+                (Keyword, "FROM"),
+                (Identifier, "users"),
+                (Keyword, "WHERE"),
+                (Identifier, "x"),
+                (Other, "="),
+                (String, "'it''s'"),
+                (Keyword, "AND"),
+                (Identifier, "y"),
+                (Other, ">"),
+                (Number, "1.5"),
+                (Comment, "-- why"),
+            ]
+        );
+        assert_eq!(
+            kinds("$1 ?? ? :name @p0 /* open"),
+            vec![
+                (Placeholder, "$1"),
+                (Placeholder, "??"),
+                (Placeholder, "?"),
+                (Placeholder, ":name"),
+                (Placeholder, "@p0"),
+                (Comment, "/* open"),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_bindings_over_their_tokens() {
+        let sql = "select 'a' where id = 5 and n = 'b'";
+        let colors = highlight(sql, &[22..23, 32..35]);
+        assert_eq!(
+            colors,
+            vec![
+                (0..6, Color::Blue),
+                (7..10, Color::Green),
+                (11..16, Color::Blue),
+                (22..23, Color::Red),
+                (24..27, Color::Blue),
+                (32..35, Color::Red),
+            ]
+        );
+    }
+
+    #[test]
+    fn fingerprints_ignore_values() {
+        assert_eq!(
+            fingerprint("SELECT * FROM users  WHERE id = 5 -- by id\n AND name = 'ann';"),
+            "select * from users where id = ? and name = ?"
+        );
+        assert_eq!(
+            fingerprint("select * from users where id in ($1, $2, $3)"),
+            fingerprint("select * from users where id in (7)")
+        );
+        assert_eq!(
+            fingerprint("insert into t (a, b) values (?, ?), (?, ?), (?, ?)"),
+            "insert into t (a, b) values (?)"
+        );
+    }
+}
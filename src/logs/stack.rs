@@ -0,0 +1,527 @@
+use std::{fmt, sync::OnceLock};
+
+use regex::Regex;
+use serde_json::Value;
+
+/// How long a record waits for more lines before being considered complete. Runtimes write
+/// stack traces all at once, so their lines arrive together
+pub const FLUSH_AFTER: std::time::Duration = std::time::Duration::from_millis(100);
+
+// Records longer than this are cut, so a runaway stream can't be held forever
+const MAX_LINES: usize = 1000;
+
+// Python starts tracebacks with this and ends them with the (not indented) exception line
+const TRACEBACK: &str = "Traceback (most recent call last):";
+const PYTHON_CHAINED: [&str; 2] = [
+    "During handling of the above exception, another exception occurred:",
+    "The above exception was the direct cause of the following exception:",
+];
+
+// Frames in the runtime itself, collapsed in detailed mode
+const INTERNAL_FRAMES: [&str; 7] = [
+    "node:internal/",
+    "(internal/",
+    "java.base/",
+    "at java.",
+    "at jdk.internal.",
+    "at sun.",
+    "<frozen ",
+];
+// Shortest run of internal frames worth collapsing
+const MIN_COLLAPSED: usize = 3;
+
+/// Joins lines that belong together (stack traces of Node, Java and Python) into a single record.
+/// Only lines that could start a trace wait for the lines following them, a record is complete
+/// when a line that doesn't continue it arrives, or when nothing arrived for `FLUSH_AFTER`
+#[derive(Debug, Default)]
+pub struct LineAssembler {
+    pending: Vec<String>,
+    /// Inside a Python traceback, waiting for the exception line that ends it
+    in_traceback: bool,
+    frames: usize,
+}
+
+impl LineAssembler {
+    pub fn new() -> LineAssembler {
+        LineAssembler::default()
+    }
+
+    /// Adds a line that was just read, returning the records it completed (if any)
+    pub fn push(&mut self, line: String) -> Vec<String> {
+        let mut done = Vec::new();
+        if self.is_waiting() && self.continues(&line) {
+            self.append(line);
+            if self.pending.len() >= MAX_LINES {
+                done.extend(self.flush());
+            }
+            return done;
+        }
+        done.extend(self.flush());
+        // Other lines (including JSON objects) are never followed by a trace, there's no point in
+        // holding them back while waiting for one
+        if could_start_trace(line.trim()) {
+            self.append(line);
+        } else {
+            done.push(line);
+        }
+        done
+    }
+
+    /// Whether there's a record that could still get more lines
+    pub fn is_waiting(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Completes the record being assembled
+    pub fn flush(&mut self) -> Option<String> {
+        self.in_traceback = false;
+        self.frames = 0;
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.pending).concat())
+    }
+
+    fn continues(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return false;
+        }
+        if trimmed == TRACEBACK
+            || PYTHON_CHAINED.contains(&trimmed)
+            || trimmed.starts_with("Caused by:")
+            || self.in_traceback
+        {
+            return true;
+        }
+        let indented = line.starts_with([' ', '\t']);
+        if indented
+            && (is_frame(trimmed)
+                || (trimmed.starts_with("... ") && trimmed.ends_with(" more"))
+                || trimmed.starts_with("Suppressed:")
+                || trimmed.starts_with("[cause]:"))
+        {
+            return true;
+        }
+        // Node prints the properties of errors after their frames, like `{ code: 'E' }`
+        self.frames > 0 && (indented || trimmed == "}")
+    }
+
+    fn append(&mut self, line: String) {
+        let trimmed = line.trim();
+        if trimmed == TRACEBACK {
+            self.in_traceback = true;
+        } else if self.in_traceback && !line.starts_with([' ', '\t']) {
+            self.in_traceback = false;
+        }
+        if is_frame(trimmed) {
+            self.frames += 1;
+        }
+        self.pending.push(line);
+    }
+}
+
+/// The lines following the first one of a record: frames, causes and whatever else the runtime
+/// printed with the error
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackTrace {
+    pub lines: Vec<String>,
+}
+
+impl StackTrace {
+    /// Splits a record into its first line and the trace following it, if there's one
+    pub fn split(record: &str) -> (&str, Option<StackTrace>) {
+        let mut lines = record.lines();
+        let first = lines.next().unwrap_or("");
+        let lines: Vec<String> = lines.map(|l| l.trim_end().to_string()).collect();
+        if lines.iter().all(|l| l.is_empty()) {
+            return (first, None);
+        }
+        (first, Some(StackTrace { lines }))
+    }
+
+    /// Renders an error object as written by pino's serializer (`type`, `message`, `stack`,
+    /// `code` and a nested `cause`): its headline and frames, followed by those of every cause
+    pub fn from_error(error: &Value) -> Option<StackTrace> {
+        let mut lines = Vec::new();
+        let mut current = Some(error);
+        while let Some(error) = current {
+            let Some(headline) = error_headline(error) else {
+                break;
+            };
+            if lines.is_empty() {
+                lines.extend(headline.lines().map(|l| l.to_string()));
+            } else {
+                lines.push(format!("Caused by: {}", headline));
+            }
+            current = error.get("cause");
+
+            let Some(stack) = error.get("stack").and_then(Value::as_str) else {
+                continue;
+            };
+            // The stack repeats the headline first. Some serializers also append the stacks of
+            // the causes, they're left out when the causes are there to be shown on their own
+            let frames: Vec<&str> = stack
+                .lines()
+                .skip_while(|l| !is_frame(l.trim()))
+                .take_while(|l| current.is_none() || l.starts_with([' ', '\t']))
+                .collect();
+            lines.extend(frames.iter().map(|l| l.trim_end().to_string()));
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        Some(StackTrace { lines })
+    }
+
+    /// Every frame pointing to a file, in order
+    pub fn locations(&self) -> Vec<Location> {
+        self.lines
+            .iter()
+            .filter(|l| is_frame(l.trim()))
+            .filter_map(|l| Location::of_frame(l))
+            .collect()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.lines.iter().filter(|l| is_frame(l.trim())).count()
+    }
+
+    /// The last exception of the trace: the one a Python traceback ends with or the root cause
+    /// of a Java one
+    fn exception(&self) -> Option<&str> {
+        self.lines.iter().rev().map(|l| l.as_str()).find(|l| {
+            !l.starts_with([' ', '\t'])
+                && !l.is_empty()
+                && !l.starts_with(['{', '}'])
+                && *l != TRACEBACK
+                && !PYTHON_CHAINED.contains(l)
+        })
+    }
+
+    /// A one line summary of a log and its trace, as shown in compact mode
+    pub fn summarize(&self, msg: &str) -> String {
+        let frames = match self.frames() {
+            1 => "1 frame".to_string(),
+            n => format!("{} frames", n),
+        };
+        match self.exception() {
+            // The header of a Python traceback says nothing, the exception does
+            Some(exception) if msg.trim() == TRACEBACK => format!("{} ({})", exception, frames),
+            Some(exception) => format!("{} ({}, {})", msg, exception, frames),
+            None => format!("{} ({})", msg, frames),
+        }
+    }
+
+    /// The lines shown in detailed mode. Long runs of frames from the runtime itself are folded
+    /// into a single line, unless `all_frames` is set
+    pub fn shown(&self, all_frames: bool) -> Vec<(LineKind, String)> {
+        if all_frames {
+            return self.lines.iter().map(|l| (kind(l), l.clone())).collect();
+        }
+        let mut shown = Vec::new();
+        let mut run: Vec<&str> = Vec::new();
+        let fold = |run: &mut Vec<&str>, shown: &mut Vec<(LineKind, String)>| {
+            if run.len() >= MIN_COLLAPSED {
+                let indent = run[0].len() - run[0].trim_start().len();
+                shown.push((
+                    LineKind::Folded,
+                    format!("{}... {} internal frames", &run[0][..indent], run.len()),
+                ));
+            } else {
+                shown.extend(run.iter().map(|l| (kind(l), l.to_string())));
+            }
+            run.clear();
+        };
+        for line in &self.lines {
+            // The last frame opens the properties of the error, it has to stay
+            if INTERNAL_FRAMES.iter().any(|i| line.contains(i)) && !line.ends_with('{') {
+                run.push(line);
+            } else {
+                fold(&mut run, &mut shown);
+                shown.push((kind(line), line.clone()));
+            }
+        }
+        fold(&mut run, &mut shown);
+        shown
+    }
+}
+
+/// Where a frame points to in the source code
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+impl Location {
+    /// Finds the location of a frame, like `at save (/app/src/users.ts:42:13)` or
+    /// `File "/app/users.py", line 42, in save`. Frames in the runtime itself (`node:internal`,
+    /// `<anonymous>`...) have none
+    pub fn of_frame(frame: &str) -> Option<Location> {
+        static NODE: OnceLock<Regex> = OnceLock::new();
+        static PYTHON: OnceLock<Regex> = OnceLock::new();
+        let frame = frame.trim();
+        let (path, line, column) = if frame.starts_with("at ") {
+            let node = NODE.get_or_init(|| {
+                Regex::new(r"(?:\(|at )(?:file://)?([^\s()]+):(\d+):(\d+)\)?$").unwrap()
+            });
+            let captures = node.captures(frame)?;
+            (captures.get(1)?, captures.get(2)?, captures.get(3))
+        } else {
+            let python =
+                PYTHON.get_or_init(|| Regex::new(r#"^File "([^"]+)", line (\d+)"#).unwrap());
+            let captures = python.captures(frame)?;
+            (captures.get(1)?, captures.get(2)?, None)
+        };
+        let path = path.as_str();
+        if !path.contains('/') || path.starts_with("node:") {
+            return None;
+        }
+        Some(Location {
+            path: path.to_string(),
+            line: line.as_str().parse().ok()?,
+            column: column.and_then(|c| c.as_str().parse().ok()),
+        })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a line of a stack trace is, which changes how it's shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    /// The type and message of an error or of one of its causes
+    Error,
+    Frame,
+    /// A frame in a dependency (`node_modules`), rarely where the problem is
+    Dependency,
+    /// Stands for several frames that were folded
+    Folded,
+    Other,
+}
+
+fn kind(line: &str) -> LineKind {
+    let trimmed = line.trim();
+    if is_frame(trimmed) {
+        return if trimmed.contains("node_modules") {
+            LineKind::Dependency
+        } else {
+            LineKind::Frame
+        };
+    }
+    let is_cause = ["Caused by:", "caused by:", "[cause]:"]
+        .iter()
+        .any(|c| trimmed.starts_with(c));
+    let is_exception = !line.starts_with([' ', '\t'])
+        && !trimmed.is_empty()
+        && !trimmed.starts_with(['{', '}'])
+        && trimmed != TRACEBACK
+        && !PYTHON_CHAINED.contains(&trimmed);
+    if is_cause || is_exception {
+        LineKind::Error
+    } else {
+        LineKind::Other
+    }
+}
+
+/// The type and message of an error object, like `TypeError: x is undefined [ERR_CODE]`
+pub fn error_headline(error: &Value) -> Option<String> {
+    if let Value::String(error) = error {
+        return error.lines().next().map(|line| line.to_string());
+    }
+    let text = |key: &str| error.get(key).and_then(Value::as_str);
+    let mut headline = match (text("type").or(text("name")), text("message")) {
+        (Some(kind), Some(message)) => format!("{}: {}", kind, message),
+        (Some(kind), None) => kind.to_string(),
+        (None, Some(message)) => message.to_string(),
+        // The stack starts with both
+        (None, None) => text("stack")?.lines().next()?.to_string(),
+    };
+    match error.get("code") {
+        Some(Value::String(code)) => headline.push_str(&format!(" [{}]", code)),
+        Some(Value::Number(code)) => headline.push_str(&format!(" [{}]", code)),
+        _ => {}
+    }
+    Some(headline)
+}
+
+/// Error headlines (`TypeError: x`, `java.lang.IllegalStateException: x`, `Exception in thread`),
+/// Node's `console.trace`, Python tracebacks and Rust panics
+fn could_start_trace(trimmed: &str) -> bool {
+    static HEADLINE: OnceLock<Regex> = OnceLock::new();
+    let headline = HEADLINE.get_or_init(|| {
+        Regex::new(r"(?i)^(?:[\w.$]*(?:error|exception|throwable)\b|trace:)").unwrap()
+    });
+    trimmed == TRACEBACK || trimmed.contains("panicked at") || headline.is_match(trimmed)
+}
+
+fn is_frame(trimmed: &str) -> bool {
+    trimmed.starts_with("at ") || trimmed.starts_with("File \"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds every line to an assembler, returning the complete records
+    fn assemble(text: &str) -> Vec<String> {
+        let mut assembler = LineAssembler::new();
+        let mut records: Vec<String> = text
+            .split_inclusive('\n')
+            .flat_map(|line| assembler.push(line.to_string()))
+            .collect();
+        records.extend(assembler.flush());
+        records
+    }
+
+    #[test]
+    fn joins_node_errors() {
+        let records = assemble(
+            "Server started\nError: connect ECONNREFUSED\n    at TCPConnectWrap.afterConnect (node:net:1494:16)\n    at Socket.emit (node:events:517:28) {\n  code: 'ECONNREFUSED'\n}\n{\"level\":30}\n",
+        );
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], "Server started\n");
+        let (first, stack) = StackTrace::split(&records[1]);
+        assert_eq!(first, "Error: connect ECONNREFUSED");
+        let stack = stack.unwrap();
+        assert_eq!(stack.frames(), 2);
+        assert_eq!(stack.lines.len(), 4);
+        assert_eq!(
+            stack.summarize(first),
+            "Error: connect ECONNREFUSED (2 frames)"
+        );
+    }
+
+    #[test]
+    fn only_waits_after_lines_that_could_start_a_trace() {
+        let mut assembler = LineAssembler::new();
+        assert_eq!(
+            assembler.push("Listening on port 3000\n".to_string()),
+            vec!["Listening on port 3000\n"]
+        );
+        assert!(!assembler.is_waiting());
+        for headline in [
+            "TypeError: x is undefined",
+            "Traceback (most recent call last):",
+            "thread 'main' panicked at src/main.rs:2:5:",
+        ] {
+            assert!(assembler.push(headline.to_string()).is_empty());
+            assert!(assembler.is_waiting());
+            assembler.flush();
+        }
+    }
+
+    #[test]
+    fn joins_java_causes() {
+        let records = assemble(
+            "Exception in thread \"main\" java.lang.RuntimeException: failed\n\tat App.main(App.java:5)\nCaused by: java.io.IOException: disk full\n\tat App.write(App.java:9)\n\t... 1 more\nnext\n",
+        );
+        assert_eq!(records.len(), 2);
+        let (first, stack) = StackTrace::split(&records[0]);
+        assert_eq!(
+            stack.unwrap().summarize(first),
+            "Exception in thread \"main\" java.lang.RuntimeException: failed (Caused by: java.io.IOException: disk full, 2 frames)"
+        );
+    }
+
+    #[test]
+    fn joins_python_tracebacks() {
+        let records = assemble(
+            "Traceback (most recent call last):\n  File \"app.py\", line 3, in <module>\n    main()\nValueError: bad value\nINFO done\n",
+        );
+        assert_eq!(records.len(), 2);
+        let (first, stack) = StackTrace::split(&records[0]);
+        assert_eq!(
+            stack.unwrap().summarize(first),
+            "ValueError: bad value (1 frame)"
+        );
+        assert_eq!(records[1], "INFO done\n");
+    }
+
+    #[test]
+    fn collapses_internal_frames() {
+        let stack = StackTrace {
+            lines: vec![
+                "    at handler (/app/src/users.js:10:5)".to_string(),
+                "    at process (node:internal/a:1:1)".to_string(),
+                "    at next (node:internal/b:1:1)".to_string(),
+                "    at run (node:internal/c:1:1)".to_string(),
+            ],
+        };
+        assert_eq!(stack.shown(true).len(), 4);
+        assert_eq!(
+            stack.shown(false),
+            vec![
+                (
+                    LineKind::Frame,
+                    "    at handler (/app/src/users.js:10:5)".to_string()
+                ),
+                (LineKind::Folded, "    ... 3 internal frames".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_frame_locations() {
+        let location = |frame| Location::of_frame(frame).map(|l| l.to_string());
+        assert_eq!(
+            location("    at UserService.save (/app/src/users/service.ts:42:13)").as_deref(),
+            Some("/app/src/users/service.ts:42:13")
+        );
+        assert_eq!(
+            location("    at file:///app/src/index.mjs:3:1").as_deref(),
+            Some("/app/src/index.mjs:3:1")
+        );
+        assert_eq!(
+            location("  File \"/app/users.py\", line 7, in save").as_deref(),
+            Some("/app/users.py:7")
+        );
+        assert_eq!(location("    at process (node:internal/a:1:1)"), None);
+        assert_eq!(location("    at new Promise (<anonymous>)"), None);
+    }
+
+    #[test]
+    fn renders_error_objects_with_their_causes() {
+        let error = serde_json::json!({
+            "type": "QueryError",
+            "message": "insert failed",
+            "code": "23505",
+            "stack": "QueryError: insert failed\n    at save (/app/src/users.js:10:5)\n    at query (/app/node_modules/pg/lib/client.js:1:1)\ncaused by: Error: duplicate key\n    at parse (/app/node_modules/pg/lib/parser.js:1:1)",
+            "cause": {
+                "type": "Error",
+                "message": "duplicate key",
+                "stack": "Error: duplicate key\n    at parse (/app/node_modules/pg/lib/parser.js:1:1)"
+            }
+        });
+        assert_eq!(
+            error_headline(&error).as_deref(),
+            Some("QueryError: insert failed [23505]")
+        );
+        let kinds: Vec<LineKind> = StackTrace::from_error(&error)
+            .unwrap()
+            .shown(false)
+            .into_iter()
+            .map(|(kind, _)| kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LineKind::Error,
+                LineKind::Frame,
+                LineKind::Dependency,
+                LineKind::Error,
+                LineKind::Dependency
+            ]
+        );
+    }
+}
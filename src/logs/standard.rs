@@ -1,5 +1,7 @@
 use crate::logs::FormattedLog;
+use crate::logs::Level;
 use crate::logs::ParsableLog;
+use crate::logs::{error_headline, RequestInfo, StackTrace, REQUEST_ID_KEYS};
 use std::collections::HashMap;
 
 use chrono::{DateTime, Local};
@@ -7,6 +9,11 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use serde_with::skip_serializing_none;
 
+// Where pino's serializer (and our own `data`) put errors
+const ERROR_PATHS: [&str; 4] = ["/err", "/error", "/data/err", "/data/error"];
+// Properties of errors that are shown in their stack trace
+const ERROR_TRACE_KEYS: [&str; 6] = ["type", "name", "message", "stack", "code", "cause"];
+
 fn default_none<T>() -> Option<T> {
     None
 }
@@ -32,30 +39,6 @@ struct InternalDataLog {
     response: Option<HashMap<String, Value>>,
 }
 
-#[skip_serializing_none]
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct InternalLogResponse {
-    status_code: i32,
-    request: Option<HashMap<String, Value>>,
-    body: Option<HashMap<String, Value>>,
-    headers: Option<HashMap<String, Value>>,
-}
-
-/// A message can be either a string or an object. So we have 2 structs that we attempt to parse
-/// the message
-#[derive(Serialize, Deserialize, Debug)]
-struct StringMsgLog {
-    msg: String,
-}
-
-/// A message can be either a string or an object. So we have 2 structs that we attempt to parse
-/// the message
-#[derive(Serialize, Deserialize, Debug)]
-struct StringObjLog {
-    msg: HashMap<String, Value>,
-}
-
 /// Main log used by us. It contains detailed information that is useful at production envs, but
 /// that isn't necessarily full of useful information when developing. The way this works it'll
 /// "match" to any json object that contains an integer "level" property
@@ -76,6 +59,45 @@ pub struct StandardLog {
     msg: Option<String>,
     #[serde(skip, default = "default_none")]
     msg_obj: Option<HashMap<String, Value>>,
+    // The whole line as it was parsed, including properties we don't know about
+    #[serde(skip)]
+    fields: Value,
+}
+
+impl StandardLog {
+    /// Parses lines that are JSON objects with a numeric `level`
+    pub fn from_line(line: &str) -> Option<Self> {
+        let fields = serde_json::from_str::<Value>(line).ok()?;
+        let mut standard_log = StandardLog::deserialize(&fields).ok()?;
+        // Standard log does not yet contain a message because it could be a string or an object.
+        // Let's see which one it is
+        match fields.get("msg") {
+            Some(Value::String(msg)) => standard_log.msg = Some(msg.clone()),
+            Some(Value::Object(msg)) => {
+                standard_log.msg_obj = Some(msg.clone().into_iter().collect());
+            }
+            _ => {}
+        }
+        standard_log.fields = fields;
+
+        Some(standard_log)
+    }
+
+    /// The error attached to the log, if there's one
+    fn error(&self) -> Option<&Value> {
+        ERROR_PATHS
+            .iter()
+            .find_map(|path| self.fields.pointer(path))
+            .filter(|error| error.is_object())
+    }
+
+    fn date(&self) -> Option<DateTime<Local>> {
+        self.time
+            .and_then(|t| {
+                DateTime::from_timestamp((t / 1000) as i64, ((t % 1000) * 1_000_000) as u32)
+            })
+            .map(|d| d.with_timezone(&Local))
+    }
 }
 
 /// Implements the Parsable log trait for standardlog which ties our representation with the rest
@@ -83,13 +105,9 @@ pub struct StandardLog {
 /// configuration
 impl ParsableLog for StandardLog {
     fn format_compact(&self) -> FormattedLog {
-        // We might have a time, but if something happens and we can't find it, use now
-        let time = match self.time {
-            Some(t) => DateTime::from_timestamp(t as i64 / 1000, 0)
-                .unwrap()
-                .with_timezone(&Local::now().timezone()),
-            None => Local::now(),
-        };
+        // We might have a time, but if something happens and we can't find it the time the line
+        // arrived is used
+        let time = self.date();
 
         // Looks for a url in the request body. Useful to see exactly what url was called. So our
         // log message could be `GET /users`, but we also want to log `GET /users?bla=xxx` and the
@@ -130,30 +148,42 @@ impl ParsableLog for StandardLog {
             log.push_str(&serde_json::to_string_pretty(&msg).unwrap_or("".to_string()));
         }
 
+        // Errors are usually logged with their own message, no need to repeat it
+        if let Some(error) = self.error() {
+            let message = error
+                .as_str()
+                .or_else(|| error.get("message").and_then(Value::as_str))
+                .filter(|message| !message.is_empty());
+            let repeated = message
+                .zip(self.msg.as_deref())
+                .is_some_and(|(message, msg)| msg.contains(message));
+            if let Some(headline) = error_headline(error).filter(|_| !repeated) {
+                log.push_str(&format!(" - {}", headline));
+            }
+        }
+
         // Useful for endpoints that respond with some sort of data in the body. This is usually
         // were we log them
         if let Some(data) = &self.data {
             if let Some(body) = &data.body {
                 extra = Some(body.clone());
-                log.push_str(&" - Responding with");
+                log.push_str(" - Responding with");
             }
         }
 
         FormattedLog {
             date: time,
+            level: self.level(),
             msg: log,
+            msg_colors: Vec::new(),
             extra,
             color_overwrite: None,
+            stack: None,
         }
     }
 
     fn format_detailed(&self) -> FormattedLog {
-        let time = match self.time {
-            Some(t) => DateTime::from_timestamp(t as i64 / 1000, 0)
-                .unwrap()
-                .with_timezone(&Local::now().timezone()),
-            None => Local::now(),
-        };
+        let time = self.date();
 
         // Looks for a url in the request body. Useful to see exactly what url was called. So our
         // log message could be `GET /users`, but we also want to log `GET /users?bla=xxx` and the
@@ -234,31 +264,95 @@ impl ParsableLog for StandardLog {
             }
         }
 
+        // The stack trace shows most of the error, the rest of its properties are kept
+        let error = self.error();
+        if let Some(Value::Object(error)) = error {
+            let rest: Map<String, Value> = error
+                .iter()
+                .filter(|(key, _)| !ERROR_TRACE_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            if !rest.is_empty() {
+                extra.insert("err".to_string(), Value::Object(rest));
+            }
+        }
+
         FormattedLog {
             date: time,
+            level: self.level(),
             msg: log,
-            extra: if extra.len() > 0 { Some(extra) } else { None },
+            msg_colors: Vec::new(),
+            extra: if !extra.is_empty() { Some(extra) } else { None },
             color_overwrite: None,
+            stack: error.and_then(StackTrace::from_error),
         }
     }
 
-    fn from_line(line: &str) -> Option<Self> {
-        match serde_json::from_str::<StandardLog>(&line) {
-            Ok(mut standard_log) => {
-                // Standard log does not yet contain a message because it could be a string or an object. Let's try
-                // to parse that
-                if let Ok(str_msg) = serde_json::from_str::<StringMsgLog>(&line) {
-                    standard_log.msg = Some(str_msg.msg);
-                } else if let Ok(msg_obj) = serde_json::from_str::<StringObjLog>(&line) {
-                    standard_log.msg_obj = Some(msg_obj.msg);
-                }
+    fn level(&self) -> Option<Level> {
+        Some(Level::from_number(self.level))
+    }
 
-                Some(standard_log)
-            }
-            Err(_e) => {
-                // println!("ERRRRRR {}", e);
-                None
-            }
-        }
+    fn fields(&self) -> &Value {
+        &self.fields
+    }
+
+    /// Requests are in `data.req`, or in `req` when logged by pino-http
+    fn request(&self) -> Option<RequestInfo> {
+        let req = self
+            .fields
+            .pointer("/data/req")
+            .or_else(|| self.fields.get("req"))
+            .filter(|req| req.is_object())?;
+        let text = |value: &Value| match value {
+            Value::String(text) => Some(text.clone()),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        };
+        let id = REQUEST_ID_KEYS
+            .iter()
+            .find_map(|key| {
+                self.fields
+                    .pointer(&format!("/data/internalContext/{}", key))
+            })
+            .or_else(|| req.get("id"))
+            .and_then(text);
+        // Responses are logged with their status (or the response itself)
+        let completed = self.data.as_ref().is_some_and(|data| {
+            data.status_code.is_some() || data.http_code.is_some() || data.response.is_some()
+        }) || ["res", "responseTime"]
+            .iter()
+            .any(|key| self.fields.get(*key).is_some());
+        Some(RequestInfo {
+            id,
+            method: req.get("method").and_then(text),
+            url: req.get("url").and_then(text),
+            completed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compact(line: &str) -> String {
+        StandardLog::from_line(line).unwrap().format_compact().msg
+    }
+
+    #[test]
+    fn shows_the_error_headline_unless_the_message_has_it() {
+        let repeated = compact(
+            r#"{"level":50,"msg":"connect ECONNREFUSED","err":{"type":"Error","message":"connect ECONNREFUSED"}}"#,
+        );
+        assert!(!repeated.contains("Error: connect"));
+        let other = compact(
+            r#"{"level":50,"msg":"Could not save","err":{"type":"Error","message":"connect ECONNREFUSED"}}"#,
+        );
+        assert!(other.ends_with(" - Could not save - Error: connect ECONNREFUSED"));
+        // Without a message there's nothing to repeat
+        let without_message = compact(
+            r#"{"level":50,"msg":"Could not save","err":{"type":"TimeoutError","code":"ETIMEDOUT"}}"#,
+        );
+        assert!(without_message.ends_with(" - Could not save - TimeoutError [ETIMEDOUT]"));
     }
 }
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+
+// ISO 8601 dates without a timezone that are common enough to be worth trying
+const ISO_WITHOUT_TIMEZONE: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// Parses a timestamp with the given format (strftime like), with or without a timezone (local
+/// time is assumed when there's none). Without a format it has to be ISO 8601
+pub fn parse_timestamp(timestamp: &str, format: Option<&str>) -> Option<DateTime<Local>> {
+    let Some(format) = format else {
+        return DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|date| date.with_timezone(&Local))
+            .or_else(|| {
+                ISO_WITHOUT_TIMEZONE
+                    .iter()
+                    .find_map(|format| parse_timestamp(timestamp, Some(format)))
+            });
+    };
+    if let Ok(date) = DateTime::parse_from_str(timestamp, format) {
+        return Some(date.with_timezone(&Local));
+    }
+    let date = NaiveDateTime::parse_from_str(timestamp, format).ok()?;
+    Local.from_local_datetime(&date).earliest()
+}
+
+/// Converts a number of seconds, milliseconds, microseconds or nanoseconds since the epoch. Which
+/// one it is is guessed from its size, assuming dates between 1973 and 5138
+pub fn from_epoch(value: f64) -> Option<DateTime<Local>> {
+    let seconds = match value.abs() {
+        v if v < 1e11 => value,
+        v if v < 1e14 => value / 1e3,
+        v if v < 1e17 => value / 1e6,
+        _ => value / 1e9,
+    };
+    let whole = seconds.floor();
+    let nanos = ((seconds - whole) * 1e9).round() as u32;
+    DateTime::from_timestamp(whole as i64, nanos.min(999_999_999)).map(|d| d.with_timezone(&Local))
+}
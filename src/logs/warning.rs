@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use termcolor::Color;
+
+use crate::logs::{FormattedLog, Level, ParsableLog};
+
+/// Something we noticed about the logs themselves (like an N+1 query), shown between them as a
+/// log of its own
+#[derive(Debug)]
+pub struct Warning {
+    msg: String,
+    /// What the warning is about, shown in detailed mode and usable in queries
+    fields: Value,
+}
+
+impl Warning {
+    pub fn new(msg: String, mut fields: Value) -> Warning {
+        if let Value::Object(object) = &mut fields {
+            object.insert("msg".to_string(), Value::String(msg.clone()));
+        }
+        Warning { msg, fields }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl ParsableLog for Warning {
+    fn format_compact(&self) -> FormattedLog {
+        FormattedLog {
+            date: None,
+            level: Some(Level::Warn),
+            msg: self.msg.clone(),
+            msg_colors: Vec::new(),
+            extra: None,
+            color_overwrite: Some(Color::Yellow),
+            stack: None,
+        }
+    }
+
+    fn format_detailed(&self) -> FormattedLog {
+        let extra: HashMap<String, Value> = match &self.fields {
+            Value::Object(object) => object
+                .iter()
+                .filter(|(key, _)| key.as_str() != "msg")
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            _ => HashMap::new(),
+        };
+        FormattedLog {
+            extra: Some(extra),
+            ..self.format_compact()
+        }
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(Level::Warn)
+    }
+
+    fn fields(&self) -> &Value {
+        &self.fields
+    }
+}
@@ -1,74 +1,197 @@
-use crate::core::{read_and_parse_logs, replay, CircularBuffer, CliState, Context, FormatType};
+use crate::cli::{Cli, Options, USAGE};
+use crate::commands::Response;
+use crate::core::{end_all_requests, replay, CliState, Context, FormatType};
+use crate::history::History;
+use crate::logs::DATE_FORMAT;
+use crate::queries::QueryStats;
+use crate::requests::{RequestTracker, DEFAULT_N_PLUS_ONE};
+use crate::session::Recorder;
+use crate::source::Source;
 use std::{
+    collections::BTreeMap,
     error::Error,
-    io::{self, BufReader},
-    process::{Command, Stdio},
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process::ExitCode,
     sync::{Arc, Mutex},
-    thread,
 };
 
+mod cli;
+mod commands;
+mod config;
 mod core;
+mod filter;
+mod history;
 mod logs;
+mod queries;
+mod query;
+mod requests;
+mod session;
+mod source;
+mod tui;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let child_shell = Command::new(&args[1])
-        .args(&args[2..])
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
+/// Where interactive commands are read from. Usually stdin, but when logs are piped to us that's
+/// taken, so we try to talk to the terminal directly
+fn command_input(sources: &[Source]) -> Option<Box<dyn BufRead>> {
+    if !sources.iter().any(|s| matches!(s, Source::Stdin)) {
+        return Some(Box::new(BufReader::new(io::stdin())));
+    }
+    File::open("/dev/tty")
+        .ok()
+        .map(|tty| Box::new(BufReader::new(tty)) as Box<dyn BufRead>)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match cli::parse(&args) {
+        Ok(Cli::Run(options)) => run(*options),
+        Ok(Cli::Help) => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Ok(Cli::Version) => {
+            println!("log-parser {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!(
+                "log-parser: {}\n\nRun log-parser --help to see how it's used",
+                e
+            );
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("log-parser: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Options) -> Result<(), Box<dyn Error>> {
+    logs::set_color_enabled(args.color);
+    let config = config::load(args.config.as_deref(), args.profile.as_deref())?;
+    // The command line wins over the config
+    let forced_parser = match &args.parser {
+        Some(name) => {
+            // Asking for a parser turns it on even if the config left it off
+            config.parsers.set_enabled(name, true)?;
+            Some(config.parsers.index_of(name)?)
+        }
+        None => None,
+    };
+    let mut text_filter = config.text_filter;
+    if let Some(filter) = args.filter {
+        text_filter.include = Some(filter);
+    }
+    let recorder = match &args.record {
+        Some(path) => Some(
+            Recorder::create(path)
+                .map_err(|e| format!("Failed to record to {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
     let context: Context = Arc::new(Mutex::new(CliState {
-        format_type: FormatType::Detailed,
-        last_logs: CircularBuffer::new(),
+        format_type: args
+            .format
+            .or(config.format)
+            .unwrap_or(FormatType::Detailed),
+        history: History::new(args.history),
+        stream_filter: None,
+        sources: Vec::new(),
+        focus: None,
+        min_level: args.min_level.or(config.min_level),
+        text_filter,
+        query: args.query,
+        live_output: !args.tui,
+        notice: None,
+        received: 0,
+        recorder,
+        parsers: Arc::new(config.parsers),
+        forced_parser,
+        detections: BTreeMap::new(),
+        redactions: Arc::new(config.redactions),
+        date_format: config
+            .date_format
+            .unwrap_or_else(|| DATE_FORMAT.to_string()),
+        all_frames: false,
+        links: config.links,
+        queries: QueryStats::default(),
+        requests: RequestTracker::new(config.n_plus_one.unwrap_or(DEFAULT_N_PLUS_ONE)),
     }));
-    let child_out = BufReader::new(child_shell.stdout.unwrap());
 
-    // So the main thread isn't hanging waiting for the server
-    let c = context.clone();
-    let child_thread = thread::spawn(move || read_and_parse_logs(child_out, c));
+    if let Some(path) = &args.open {
+        let count = session::open(path, &mut context.lock().unwrap())
+            .map_err(|e| format!("Failed to open session {}: {}", path.display(), e))?;
+        if !args.tui {
+            println!(
+                "--------- Opened {} lines from {} ----------",
+                count,
+                path.display()
+            );
+            replay(&context);
+        }
+    }
 
-    loop {
+    let sources = args.sources;
+    let tui = args.tui;
+    let mut input_reader = command_input(&sources);
+    let mut running = Vec::new();
+    for source in sources {
+        let index = context.lock().unwrap().add_source(&source.label());
+        running.push(source.start(index, &context)?);
+    }
+
+    if tui {
+        tui::run(&context)?;
+        for source in running {
+            source.stop()?;
+        }
+        return Ok(());
+    }
+
+    while let Some(reader) = input_reader.as_mut() {
         // Read user input
         let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read line");
-
-        // Trim whitespace and newlines from the input
-        let input = input.trim();
-        if input.to_lowercase() == "exit" {
+        let read = reader.read_line(&mut input).expect("Failed to read line");
+        // Input was closed, there won't be any more commands so just wait for the sources
+        if read == 0 {
             break;
         }
-        if input.to_lowercase() == "a" {
-            println!("logs are {:#?}", context.lock().unwrap().get_logs());
-        }
-        if input.starts_with("c:") {
-            match input.replace("c:", "").trim() {
-                "compact" => {
-                    context.lock().unwrap().format_type = FormatType::Compact;
-                    println!("Setting mode to compact logs");
-                }
-                "detailed" => {
-                    context.lock().unwrap().format_type = FormatType::Detailed;
-                    println!("Setting mode to detailed logs");
-                }
-                "raw" => {
-                    context.lock().unwrap().format_type = FormatType::Raw;
-                    println!("Setting mode to raw logs");
+
+        match commands::execute(&input, &context) {
+            Response::Nothing => {}
+            Response::Message(message) => println!("{}", message),
+            Response::Replay => {
+                // Clear terminal first
+                print!("{esc}c", esc = 27 as char);
+                println!("--------- Replaying ----------");
+                replay(&context);
+            }
+            Response::Open(location) => {
+                // The editor gets the terminal for itself, lines are still kept in history
+                context.lock().unwrap().live_output = false;
+                let opened = logs::open_in_editor(&location);
+                context.lock().unwrap().live_output = true;
+                if let Err(e) = opened {
+                    println!("{}", e);
                 }
-                "replay" => {
-                    // Clear terminal first
-                    print!("{esc}c", esc = 27 as char);
-                    println!("--------- Replaying ----------");
-                    replay(&context);
+            }
+            Response::Exit => {
+                for source in running {
+                    source.stop()?;
                 }
-                _ => println!("Unknown format"),
+                end_all_requests(&context);
+                return Ok(());
             }
-            continue;
         }
     }
 
-    child_thread.join().unwrap();
+    for source in running {
+        source.wait()?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Local};
+
+use crate::logs::{fingerprint, QueryEvent};
+
+/// Durations kept per query to compute percentiles. Older ones still count for the min and avg
+const MAX_SAMPLES: usize = 1000;
+
+/// Queries that never got a response are forgotten after this many minutes
+const PENDING_MINUTES: i64 = 5;
+
+// When queries were first and last seen, they're usually from the same day
+const TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Default number of queries listed by `c: queries`
+pub const DEFAULT_TOP: usize = 10;
+
+/// Everything known about the queries sharing a fingerprint
+#[derive(Debug, Clone)]
+pub struct QueryStat {
+    pub fingerprint: String,
+    pub count: usize,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    /// How many of them have a duration
+    timed: usize,
+    total_ms: f64,
+    min_ms: f64,
+    /// Most recent durations
+    samples: VecDeque<f64>,
+}
+
+impl QueryStat {
+    fn new(fingerprint: String, at: DateTime<Local>) -> QueryStat {
+        QueryStat {
+            fingerprint,
+            count: 0,
+            first_seen: at,
+            last_seen: at,
+            timed: 0,
+            total_ms: 0.0,
+            min_ms: f64::INFINITY,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn add_duration(&mut self, ms: f64) {
+        self.timed += 1;
+        self.total_ms += ms;
+        self.min_ms = self.min_ms.min(ms);
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.timed > 0).then_some(self.min_ms)
+    }
+
+    pub fn avg(&self) -> Option<f64> {
+        (self.timed > 0).then(|| self.total_ms / self.timed as f64)
+    }
+
+    pub fn p95(&self) -> Option<f64> {
+        let mut sorted: Vec<f64> = self.samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        // Nearest rank
+        let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+        sorted.get(rank.checked_sub(1)?).copied()
+    }
+
+    /// Time spent in these queries, as far as we know
+    pub fn total(&self) -> f64 {
+        self.total_ms
+    }
+}
+
+/// Queries seen since we started, grouped by fingerprint
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    by_fingerprint: HashMap<String, QueryStat>,
+    /// Queries that started and are waiting for a response, by uid: their fingerprint and when
+    /// they started
+    pending: HashMap<String, (String, DateTime<Local>)>,
+}
+
+impl QueryStats {
+    /// Counts a query logged at `at`. When the same uid shows up again (knex logs `query` and then
+    /// `query-response` or `query-error`) it's the end of the first one, so it's timed instead of
+    /// counted again
+    pub fn record(&mut self, event: &QueryEvent, at: DateTime<Local>) {
+        if let Some((fingerprint, started)) = event.uid.and_then(|uid| self.pending.remove(uid)) {
+            let ms = event
+                .duration
+                .unwrap_or_else(|| (at - started).num_microseconds().unwrap_or(0) as f64 / 1000.0);
+            if let Some(stat) = self.by_fingerprint.get_mut(&fingerprint) {
+                stat.add_duration(ms);
+            }
+            return;
+        }
+
+        let fingerprint = fingerprint(event.sql);
+        let stat = self
+            .by_fingerprint
+            .entry(fingerprint.clone())
+            .or_insert_with(|| QueryStat::new(fingerprint.clone(), at));
+        stat.count += 1;
+        stat.last_seen = at;
+        match (event.duration, event.uid) {
+            (Some(ms), _) => stat.add_duration(ms),
+            (None, Some(uid)) => {
+                self.pending
+                    .retain(|_, (_, started)| at - *started < Duration::minutes(PENDING_MINUTES));
+                self.pending.insert(uid.to_string(), (fingerprint, at));
+            }
+            (None, None) => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_fingerprint.is_empty()
+    }
+
+    /// The `n` queries that ran the most
+    pub fn top_by_count(&self, n: usize) -> Vec<&QueryStat> {
+        let mut stats: Vec<&QueryStat> = self.by_fingerprint.values().collect();
+        stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.first_seen.cmp(&b.first_seen)));
+        stats.truncate(n);
+        stats
+    }
+
+    /// The `n` queries the most time was spent in. Queries without durations are left out
+    pub fn top_by_time(&self, n: usize) -> Vec<&QueryStat> {
+        let mut stats: Vec<&QueryStat> = self
+            .by_fingerprint
+            .values()
+            .filter(|stat| stat.timed > 0)
+            .collect();
+        stats.sort_by(|a, b| b.total().total_cmp(&a.total()));
+        stats.truncate(n);
+        stats
+    }
+
+    /// What `c: queries` prints
+    pub fn report(&self, n: usize) -> String {
+        if self.is_empty() {
+            return "No queries seen yet".to_string();
+        }
+        let total: usize = self.by_fingerprint.values().map(|stat| stat.count).sum();
+        let mut message = format!(
+            "Queries: {} total, {} distinct\nBy count:",
+            total,
+            self.by_fingerprint.len()
+        );
+        for stat in self.top_by_count(n) {
+            message.push_str(&format!(
+                "\n  {:>6}x  {} - {}  {}",
+                stat.count,
+                stat.first_seen.format(TIME_FORMAT),
+                stat.last_seen.format(TIME_FORMAT),
+                stat.fingerprint
+            ));
+        }
+        let by_time = self.top_by_time(n);
+        if by_time.is_empty() {
+            message.push_str("\nNo durations logged (knex query-response events or a duration)");
+            return message;
+        }
+        message.push_str("\nBy time (total, min / avg / p95):");
+        let ms = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.1}", v));
+        for stat in by_time {
+            message.push_str(&format!(
+                "\n  {:>8.1}ms  {} / {} / {}ms  {}x  {}",
+                stat.total(),
+                ms(stat.min()),
+                ms(stat.avg()),
+                ms(stat.p95()),
+                stat.count,
+                stat.fingerprint
+            ));
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query<'a>(sql: &'a str, uid: Option<&'a str>) -> QueryEvent<'a> {
+        QueryEvent {
+            sql,
+            uid,
+            request_id: None,
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn groups_queries_by_fingerprint() {
+        let mut stats = QueryStats::default();
+        let start = Local::now();
+        stats.record(&query("select * from users where id = 1", None), start);
+        stats.record(&query("select * from users where id = 2", None), start);
+        stats.record(&query("select 1", None), start);
+        let top = stats.top_by_count(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].fingerprint, "select * from users where id = ?");
+        assert_eq!(top[0].count, 2);
+        assert!(stats.top_by_time(10).is_empty());
+    }
+
+    #[test]
+    fn times_queries_by_pairing_their_uid() {
+        let mut stats = QueryStats::default();
+        let start = Local::now();
+        for (i, ms) in [10, 20, 30].into_iter().enumerate() {
+            let uid = i.to_string();
+            stats.record(&query("select 1", Some(&uid)), start);
+            stats.record(
+                &query("select 1", Some(&uid)),
+                start + Duration::milliseconds(ms),
+            );
+        }
+        // Explicit durations win
+        stats.record(
+            &QueryEvent {
+                sql: "select 1",
+                uid: None,
+                request_id: None,
+                duration: Some(100.0),
+            },
+            start,
+        );
+        let stat = stats.top_by_time(1)[0];
+        assert_eq!(stat.count, 4);
+        assert_eq!(stat.min(), Some(10.0));
+        assert_eq!(stat.avg(), Some(40.0));
+        assert_eq!(stat.p95(), Some(100.0));
+    }
+}
@@ -0,0 +1,520 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use regex::Regex;
+use serde_json::Value;
+
+/// A parsed query that can be evaluated against the fields of a log, like
+/// `level>=50 and data.httpCode=500 and data.req.url~"/users"`
+///
+/// Grammar:
+/// - `a and b`, `a or b`, `not a` and parenthesis, `and` binds tighter than `or`
+/// - `path op value` where `path` is a dot separated list of keys (or array indexes) and `op` is
+///   one of `=`, `!=`, `>`, `>=`, `<`, `<=`, `~` (regex match) or `!~` (regex doesn't match)
+/// - `path` alone checks that the field exists and isn't `null` or `false`
+/// - values are numbers, `true`, `false`, `null`, quoted strings or bare words
+#[derive(Debug)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: source.chars().count(),
+        };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(QueryError::new(
+                format!("Unexpected {}", token.kind),
+                token.position,
+            ));
+        }
+        Ok(Query {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// Whether the fields of a log match the query
+    pub fn matches(&self, fields: &Value) -> bool {
+        self.expr.eval(fields)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Why a query couldn't be parsed, with the position (in characters) where it happened
+#[derive(Debug)]
+pub struct QueryError {
+    message: String,
+    position: usize,
+}
+
+impl QueryError {
+    fn new(message: String, position: usize) -> QueryError {
+        QueryError { message, position }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Vec<String>),
+    Compare(Vec<String>, Op, Literal),
+    Match(Vec<String>, Regex, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    NotEq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+#[derive(Debug)]
+enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Null,
+}
+
+impl Expr {
+    fn eval(&self, fields: &Value) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(fields) && b.eval(fields),
+            Expr::Or(a, b) => a.eval(fields) || b.eval(fields),
+            Expr::Not(a) => !a.eval(fields),
+            Expr::Exists(path) => !matches!(
+                lookup(fields, path),
+                None | Some(Value::Null) | Some(Value::Bool(false))
+            ),
+            Expr::Compare(path, op, literal) => {
+                let value = lookup(fields, path);
+                match op {
+                    Op::Eq => value.is_some_and(|v| equals(v, literal)),
+                    Op::NotEq => !value.is_some_and(|v| equals(v, literal)),
+                    _ => match value.and_then(|v| compare(v, literal)) {
+                        Some(ordering) => match op {
+                            Op::Gt => ordering == Ordering::Greater,
+                            Op::Gte => ordering != Ordering::Less,
+                            Op::Lt => ordering == Ordering::Less,
+                            _ => ordering != Ordering::Greater,
+                        },
+                        None => false,
+                    },
+                }
+            }
+            Expr::Match(path, regex, negated) => {
+                let matched = lookup(fields, path).is_some_and(|v| regex.is_match(&as_text(v)));
+                matched != *negated
+            }
+        }
+    }
+}
+
+fn lookup<'a>(fields: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(fields, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Text used when matching a value against a string or a regex. Strings aren't quoted
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn equals(value: &Value, literal: &Literal) -> bool {
+    match (value, literal) {
+        (Value::Number(n), Literal::Number(l)) => n.as_f64() == Some(*l),
+        (Value::Bool(b), Literal::Bool(l)) => b == l,
+        (Value::Null, Literal::Null) => true,
+        (value, Literal::String(l)) => as_text(value) == *l,
+        // A number written as a string in the log should still match `field=10`
+        (Value::String(s), Literal::Number(l)) => s.parse::<f64>().ok() == Some(*l),
+        _ => false,
+    }
+}
+
+/// Numbers compare numerically and strings lexically (which works for ISO dates), anything else
+/// can't be ordered
+fn compare(value: &Value, literal: &Literal) -> Option<Ordering> {
+    match (value, literal) {
+        (Value::Number(n), Literal::Number(l)) => n.as_f64()?.partial_cmp(l),
+        (Value::String(s), Literal::Number(l)) => s.parse::<f64>().ok()?.partial_cmp(l),
+        (Value::String(s), Literal::String(l)) => Some(s.as_str().cmp(l.as_str())),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Op(&'static str),
+    Quoted(String),
+    Word(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::LParen => write!(f, "`(`"),
+            TokenKind::RParen => write!(f, "`)`"),
+            TokenKind::Op(op) => write!(f, "`{}`", op),
+            TokenKind::Quoted(s) => write!(f, "\"{}\"", s),
+            TokenKind::Word(w) => write!(f, "`{}`", w),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+// Longer operators first so `>=` isn't read as `>` followed by `=`
+const OPERATORS: [&str; 9] = ["==", "!=", "!~", ">=", "<=", "=", ">", "<", "~"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, QueryError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let position = i;
+        if c == '(' || c == ')' {
+            let kind = if c == '(' {
+                TokenKind::LParen
+            } else {
+                TokenKind::RParen
+            };
+            tokens.push(Token { kind, position });
+            i += 1;
+            continue;
+        }
+        if let Some(op) = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(j, o)| chars.get(i + j) == Some(&o))
+        }) {
+            tokens.push(Token {
+                kind: TokenKind::Op(op),
+                position,
+            });
+            i += op.len();
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err(QueryError::new("Unterminated string".to_string(), position))
+                    }
+                    Some('\\') => {
+                        if let Some(escaped) = chars.get(i + 1) {
+                            value.push(*escaped);
+                        }
+                        i += 2;
+                    }
+                    Some(q) if *q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(other) => {
+                        value.push(*other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::Quoted(value),
+                position,
+            });
+            continue;
+        }
+        let mut word = String::new();
+        while let Some(&c) = chars.get(i) {
+            if c.is_whitespace() || "()=!<>~\"'".contains(c) {
+                break;
+            }
+            word.push(c);
+            i += 1;
+        }
+        // Like a `!` that isn't part of `!=` or `!~`
+        if word.is_empty() {
+            return Err(QueryError::new(format!("Unexpected `{}`", c), position));
+        }
+        tokens.push(Token {
+            kind: TokenKind::Word(word),
+            position,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // Length of the query, used to point at the end when something is missing
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Word(w), .. }) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_unary()?;
+        while self.peek_keyword("and") {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        if self.peek_keyword("not") {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        let end = self.end;
+        let Some(token) = self.next() else {
+            return Err(QueryError::new("Expected a condition".to_string(), end));
+        };
+        match &token.kind {
+            TokenKind::LParen => {
+                let open = token.position;
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => Ok(expr),
+                    _ => Err(QueryError::new("Unclosed `(`".to_string(), open)),
+                }
+            }
+            TokenKind::Word(path) => {
+                let path: Vec<String> = path.split('.').map(|k| k.to_string()).collect();
+                self.parse_comparison(path)
+            }
+            other => Err(QueryError::new(
+                format!("Expected a field name, found {}", other),
+                token.position,
+            )),
+        }
+    }
+
+    fn parse_comparison(&mut self, path: Vec<String>) -> Result<Expr, QueryError> {
+        let (op, op_position) = match self.peek() {
+            Some(Token {
+                kind: TokenKind::Op(op),
+                position,
+            }) => (*op, *position),
+            _ => return Ok(Expr::Exists(path)),
+        };
+        self.position += 1;
+        let end = self.end;
+        let Some(token) = self.next() else {
+            return Err(QueryError::new(
+                format!("Expected a value after `{}`", op),
+                end,
+            ));
+        };
+        let position = token.position;
+        // Operators right after each other are a single one we don't know, like `=>`
+        if let TokenKind::Op(next) = &token.kind {
+            if position == op_position + op.len() {
+                return Err(QueryError::new(
+                    format!("Unknown operator `{}{}`", op, next),
+                    op_position,
+                ));
+            }
+        }
+        let (text, quoted) = match &token.kind {
+            TokenKind::Quoted(s) => (s.clone(), true),
+            TokenKind::Word(w) => (w.clone(), false),
+            other => {
+                return Err(QueryError::new(
+                    format!("Expected a value after `{}`, found {}", op, other),
+                    position,
+                ))
+            }
+        };
+        let op = match op {
+            "=" | "==" => Op::Eq,
+            "!=" => Op::NotEq,
+            ">" => Op::Gt,
+            ">=" => Op::Gte,
+            "<" => Op::Lt,
+            "<=" => Op::Lte,
+            _ => {
+                let regex = Regex::new(&text).map_err(|e| {
+                    QueryError::new(format!("Invalid regex `{}`: {}", text, e), position)
+                })?;
+                return Ok(Expr::Match(path, regex, op == "!~"));
+            }
+        };
+        let literal = match text.as_str() {
+            _ if quoted => Literal::String(text),
+            "true" => Literal::Bool(true),
+            "false" => Literal::Bool(false),
+            "null" => Literal::Null,
+            _ => match text.parse::<f64>() {
+                Ok(n) => Literal::Number(n),
+                Err(_) => Literal::String(text),
+            },
+        };
+        Ok(Expr::Compare(path, op, literal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log() -> Value {
+        json!({
+            "level": 50,
+            "msg": "user not found",
+            "time": "2024-01-31T10:20:30Z",
+            "code": "404",
+            "data": {
+                "httpCode": 500,
+                "retried": false,
+                "req": { "url": "/users/42", "method": "GET" },
+                "items": [{ "id": 7 }, { "id": 8 }]
+            }
+        })
+    }
+
+    fn matches(query: &str) -> bool {
+        Query::parse(query).unwrap().matches(&log())
+    }
+
+    fn error(query: &str) -> String {
+        Query::parse(query).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(matches("level=10 and msg~x or data.httpCode=500"));
+        assert!(!matches("level=10 and (msg~x or data.httpCode=500)"));
+        assert!(matches("not level=10 and data.httpCode=500"));
+        assert!(!matches("not (level=50 or level=10)"));
+        assert!(matches("NOT level < 40 AND (code=404 OR missing)"));
+    }
+
+    #[test]
+    fn compares_numbers_and_strings() {
+        assert!(matches("level=50") && matches("level==50"));
+        assert!(matches("level!=40") && !matches("level!=50"));
+        assert!(matches("level>40") && !matches("level>50"));
+        assert!(matches("level>=50") && !matches("level>=51"));
+        assert!(matches("level<51") && !matches("level<50"));
+        assert!(matches("level<=50") && !matches("level<=49"));
+        // Numbers logged as strings still compare as numbers
+        assert!(matches("code=404") && matches("code>99"));
+        // Strings compare lexically, which works for ISO dates
+        assert!(matches(r#"time>"2024-01-01""#) && !matches("time<2024-01-01"));
+        assert!(matches("data.retried=false") && matches("missing!=1"));
+        assert!(!matches("msg>5"));
+    }
+
+    #[test]
+    fn matches_regexes() {
+        assert!(matches(r#"data.req.url~"^/users/[0-9]+$""#));
+        assert!(!matches("data.req.url~^/posts"));
+        assert!(matches("data.req.url!~^/posts"));
+        assert!(!matches("missing~."));
+        assert!(matches("missing!~."));
+    }
+
+    #[test]
+    fn follows_nested_paths_and_array_indexes() {
+        assert!(matches("data.items.1.id=8"));
+        assert!(!matches("data.items.2.id"));
+        assert!(matches("data.req") && !matches("data.retried"));
+        assert!(!matches("data.req.url.deeper"));
+    }
+
+    #[test]
+    fn points_at_what_is_wrong() {
+        assert_eq!(
+            error(r#"msg="not closed"#),
+            "Unterminated string at position 5"
+        );
+        assert_eq!(error("(level=50 or msg~x"), "Unclosed `(` at position 1");
+        assert_eq!(
+            error("level>="),
+            "Expected a value after `>=` at position 8"
+        );
+        assert_eq!(error("level=50 and"), "Expected a condition at position 13");
+        assert_eq!(error("level => 5"), "Unknown operator `=>` at position 7");
+        assert_eq!(error("level ! 5"), "Unexpected `!` at position 7");
+        assert_eq!(
+            error("level=50 level=40"),
+            "Unexpected `level` at position 10"
+        );
+        assert!(error(r#"msg~"(""#).starts_with("Invalid regex `(`"));
+        assert_eq!(
+            error("msg~(x)"),
+            "Expected a value after `~`, found `(` at position 5"
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::json;
+
+use crate::logs::{fingerprint, ParsableLog, Warning};
+
+/// How many times a query can run within one request before it's reported as an N+1
+pub const DEFAULT_N_PLUS_ONE: usize = 5;
+
+/// Requests are forgotten (and checked) once more than this many are waiting for their response
+const MAX_OPEN: usize = 100;
+
+/// A request whose logs are still arriving and the queries that ran for it so far
+#[derive(Debug)]
+struct OpenRequest {
+    endpoint: String,
+    /// How many times each fingerprint ran
+    queries: HashMap<String, usize>,
+}
+
+impl OpenRequest {
+    fn new(endpoint: String) -> OpenRequest {
+        OpenRequest {
+            endpoint,
+            queries: HashMap::new(),
+        }
+    }
+}
+
+/// What's known about the requests of one source
+#[derive(Debug, Default)]
+struct SourceRequests {
+    /// Requests that started and didn't complete yet, by id (or endpoint), oldest first
+    open: Vec<(String, OpenRequest)>,
+    /// Queries since the last request log. They belong to the request that started last, or to
+    /// the next one that completes when it didn't start in the logs (like with pino-http, which
+    /// only logs `request completed`)
+    batch: HashMap<String, usize>,
+    /// Uids of the queries counted, so their response isn't counted again
+    uids: HashSet<String>,
+}
+
+impl SourceRequests {
+    fn position(&self, key: &str) -> Option<usize> {
+        self.open.iter().position(|(open, _)| open == key)
+    }
+
+    /// Gives the queries since the last request log to the request that started last. They're
+    /// dropped when none did
+    fn assign_batch(&mut self) {
+        let batch = std::mem::take(&mut self.batch);
+        if let Some((_, request)) = self.open.last_mut() {
+            add_queries(&mut request.queries, batch);
+        }
+    }
+}
+
+/// Follows the HTTP requests of each source to find queries that run over and over within one.
+/// Requests are logs with `data.req` (or pino-http's `req`). Queries logged with a request id
+/// belong to that request, the rest belong to the request that started before them or, when the
+/// request wasn't logged when it started, to the one that completes after them
+#[derive(Debug)]
+pub struct RequestTracker {
+    /// 0 turns detection off
+    threshold: usize,
+    sources: HashMap<usize, SourceRequests>,
+}
+
+impl RequestTracker {
+    pub fn new(threshold: usize) -> RequestTracker {
+        RequestTracker {
+            threshold,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Takes a log of a source into account. Returns a warning for every query that ran more
+    /// times than the threshold in a request that just ended
+    pub fn observe(&mut self, source: usize, log: &dyn ParsableLog) -> Vec<Warning> {
+        if self.threshold == 0 {
+            return Vec::new();
+        }
+        if let Some(query) = log.query() {
+            let requests = self.sources.entry(source).or_default();
+            // Seeing a uid again means the query got a response
+            if query
+                .uid
+                .is_some_and(|uid| !requests.uids.insert(uid.to_string()))
+            {
+                return Vec::new();
+            }
+            let index = query.request_id.and_then(|id| requests.position(id));
+            let queries = match index {
+                Some(index) => &mut requests.open[index].1.queries,
+                None => &mut requests.batch,
+            };
+            *queries.entry(fingerprint(query.sql)).or_default() += 1;
+            return Vec::new();
+        }
+
+        let Some(request) = log.request() else {
+            return Vec::new();
+        };
+        let requests = self.sources.entry(source).or_default();
+        let endpoint = request.endpoint();
+        let key = request.id.unwrap_or_else(|| endpoint.clone());
+        let index = requests.position(&key);
+        if request.completed {
+            let mut open = match index {
+                Some(index) => requests.open.remove(index).1,
+                None => OpenRequest::new(endpoint),
+            };
+            add_queries(&mut open.queries, std::mem::take(&mut requests.batch));
+            if requests.open.is_empty() {
+                requests.uids.clear();
+            }
+            return self.check(open);
+        }
+
+        requests.assign_batch();
+        match index {
+            // Logged again while running, it's now the request that started last
+            Some(index) => {
+                let open = requests.open.remove(index);
+                requests.open.push(open);
+            }
+            None => requests.open.push((key, OpenRequest::new(endpoint))),
+        }
+        if requests.open.len() > MAX_OPEN {
+            let (_, oldest) = requests.open.remove(0);
+            return self.check(oldest);
+        }
+        Vec::new()
+    }
+
+    /// Ends every request of a source that's still open, like when it stops logging
+    pub fn finish(&mut self, source: usize) -> Vec<Warning> {
+        let Some(mut requests) = self.sources.remove(&source) else {
+            return Vec::new();
+        };
+        requests.assign_batch();
+        requests
+            .open
+            .into_iter()
+            .flat_map(|(_, open)| self.check(open))
+            .collect()
+    }
+
+    /// Sources that have requests being followed
+    pub fn sources(&self) -> Vec<usize> {
+        self.sources.keys().copied().collect()
+    }
+
+    fn check(&self, request: OpenRequest) -> Vec<Warning> {
+        if self.threshold == 0 {
+            return Vec::new();
+        }
+        let mut repeated: Vec<(String, usize)> = request
+            .queries
+            .into_iter()
+            .filter(|(_, count)| *count > self.threshold)
+            .collect();
+        repeated.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        repeated
+            .into_iter()
+            .map(|(fingerprint, count)| {
+                Warning::new(
+                    format!(
+                        "Possible N+1: query ran {} times in {}: {}",
+                        count, request.endpoint, fingerprint
+                    ),
+                    json!({
+                        "fingerprint": fingerprint,
+                        "count": count,
+                        "url": request.endpoint,
+                    }),
+                )
+            })
+            .collect()
+    }
+}
+
+fn add_queries(to: &mut HashMap<String, usize>, queries: HashMap<String, usize>) {
+    for (fingerprint, count) in queries {
+        *to.entry(fingerprint).or_default() += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{FormattedLog, Parser, QueryEvent, RequestInfo};
+    use serde_json::Value;
+
+    #[derive(Debug)]
+    enum Log {
+        /// A request starting, with its id and url
+        Request(&'static str, &'static str),
+        /// The same request completing
+        Completed(&'static str, &'static str),
+        Query(&'static str, Option<&'static str>),
+    }
+
+    impl ParsableLog for Log {
+        fn format_compact(&self) -> FormattedLog {
+            FormattedLog {
+                date: None,
+                level: None,
+                msg: format!("{:?}", self),
+                msg_colors: Vec::new(),
+                extra: None,
+                color_overwrite: None,
+                stack: None,
+            }
+        }
+        fn format_detailed(&self) -> FormattedLog {
+            self.format_compact()
+        }
+        fn fields(&self) -> &Value {
+            &Value::Null
+        }
+        fn query(&self) -> Option<QueryEvent<'_>> {
+            match self {
+                Log::Query(sql, uid) => Some(QueryEvent {
+                    sql,
+                    uid: *uid,
+                    request_id: None,
+                    duration: None,
+                }),
+                _ => None,
+            }
+        }
+        fn request(&self) -> Option<RequestInfo> {
+            let (id, url, completed) = match self {
+                Log::Request(id, url) => (id, url, false),
+                Log::Completed(id, url) => (id, url, true),
+                Log::Query(..) => return None,
+            };
+            Some(RequestInfo {
+                id: Some(id.to_string()),
+                method: Some("GET".to_string()),
+                url: Some(url.to_string()),
+                completed,
+            })
+        }
+    }
+
+    fn observe(tracker: &mut RequestTracker, logs: &[Log]) -> Vec<String> {
+        logs.iter()
+            .flat_map(|log| tracker.observe(0, log))
+            .map(|warning| warning.msg().to_string())
+            .collect()
+    }
+
+    fn messages(warnings: Vec<Warning>) -> Vec<String> {
+        warnings
+            .iter()
+            .map(|warning| warning.msg().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn flags_queries_repeated_within_a_request() {
+        let mut tracker = RequestTracker::new(2);
+        let logs = [
+            Log::Request("1", "/users"),
+            Log::Query("select * from users", None),
+            Log::Query("select * from posts where user_id = 1", None),
+            Log::Query("select * from posts where user_id = 2", None),
+            Log::Query("select * from posts where user_id = 3", None),
+            // The response of a query isn't another query
+            Log::Query("select * from users", Some("a")),
+            Log::Query("select * from users", Some("a")),
+            Log::Completed("1", "/users"),
+        ];
+        assert_eq!(
+            observe(&mut tracker, &logs),
+            vec!["Possible N+1: query ran 3 times in GET /users: select * from posts where user_id = ?"]
+        );
+    }
+
+    #[test]
+    fn checks_open_requests_when_the_source_ends() {
+        let mut tracker = RequestTracker::new(1);
+        let logs = [
+            Log::Request("1", "/a"),
+            Log::Query("select 1", None),
+            Log::Query("select 1", None),
+        ];
+        assert!(observe(&mut tracker, &logs).is_empty());
+        assert_eq!(
+            messages(tracker.finish(0)),
+            vec!["Possible N+1: query ran 2 times in GET /a: select ?"]
+        );
+        assert!(tracker.finish(0).is_empty());
+    }
+
+    #[test]
+    fn queries_before_a_completion_belong_to_it() {
+        // pino-http only logs requests once they complete, after their queries
+        let lines = [
+            r#"{"sql":"select * from \"posts\" where \"user_id\" = ?","bindings":[1]}"#,
+            r#"{"sql":"select * from \"posts\" where \"user_id\" = ?","bindings":[2]}"#,
+            r#"{"level":30,"time":1700000000000,"pid":7,"hostname":"api","req":{"id":1,"method":"GET","url":"/users","headers":{}},"res":{"statusCode":200,"headers":{}},"responseTime":12,"msg":"request completed"}"#,
+            r#"{"sql":"select * from \"tags\"","bindings":[]}"#,
+            r#"{"level":30,"time":1700000000050,"pid":7,"hostname":"api","req":{"id":2,"method":"GET","url":"/tags","headers":{}},"res":{"statusCode":200,"headers":{}},"responseTime":3,"msg":"request completed"}"#,
+        ];
+        let mut tracker = RequestTracker::new(1);
+        let warnings: Vec<String> = lines
+            .iter()
+            .map(|line| {
+                Parser::Knex
+                    .parse(line)
+                    .or_else(|| Parser::Standard.parse(line))
+                    .unwrap()
+            })
+            .flat_map(|log| tracker.observe(0, log.as_ref()))
+            .map(|warning| warning.msg().to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                r#"Possible N+1: query ran 2 times in GET /users: select * from "posts" where "user_id" = ?"#
+            ]
+        );
+        assert!(tracker.finish(0).is_empty());
+    }
+}
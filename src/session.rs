@@ -0,0 +1,206 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::core::{CliState, LogLine, Stream};
+use crate::filter::redact;
+
+/// One line of a session file. Sessions are NDJSON, one record per line:
+/// `{"time":"2024-01-31T10:00:00.123+01:00","source":"api","stream":"stdout","line":"..."}`
+/// - `time` is when the line arrived, in RFC 3339
+/// - `source` is the label of where the line came from (the command name, `--cmd` name, file...)
+/// - `stream` is either `stdout` or `stderr`
+/// - `line` is the line exactly as it was read, without the trailing newline. Stack traces are a
+///   single record, with their lines separated by newlines
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionRecord {
+    time: String,
+    source: String,
+    stream: Stream,
+    line: String,
+}
+
+impl SessionRecord {
+    fn new(log: &LogLine, state: &CliState) -> SessionRecord {
+        SessionRecord {
+            time: log.received_at.to_rfc3339(),
+            source: state
+                .sources
+                .get(log.source)
+                .map(|s| s.label.clone())
+                .unwrap_or_default(),
+            stream: log.stream,
+            line: log.line.trim_end_matches(['\r', '\n']).to_string(),
+        }
+    }
+}
+
+/// Appends every line to a session file as it arrives (`--record`)
+#[derive(Debug)]
+pub struct Recorder {
+    writer: LineWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Recorder, Box<dyn Error>> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            writer: LineWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, log: &LogLine, state: &CliState) -> Result<(), Box<dyn Error>> {
+        write_record(&mut self.writer, log, state)
+    }
+}
+
+fn write_record<W: Write>(
+    writer: &mut W,
+    log: &LogLine,
+    state: &CliState,
+) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer(&mut *writer, &SessionRecord::new(log, state))?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Writes everything in history to a session file, replacing it if it exists. Returns how many
+/// lines were written
+pub fn save(path: &Path, state: &CliState) -> Result<usize, Box<dyn Error>> {
+    let mut writer = std::io::BufWriter::new(File::create(path)?);
+    let mut count = 0;
+    for log in state.history.iter().filter(|log| !log.generated) {
+        write_record(&mut writer, log, state)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Loads a session file into history, as if its lines had just been read. Sources are matched by
+/// label, the ones that don't exist yet are added. Returns how many lines were loaded
+pub fn open(path: &Path, state: &mut CliState) -> Result<usize, Box<dyn Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: SessionRecord = serde_json::from_str(&line)
+            .map_err(|e| format!("Invalid session record at line {}: {}", i + 1, e))?;
+        let received_at = DateTime::parse_from_rfc3339(&record.time)
+            .map_err(|e| format!("Invalid time at line {}: {}", i + 1, e))?
+            .with_timezone(&Local);
+        let source = match state.find_source(&record.source) {
+            Some(source) => source,
+            None => state.add_source(&record.source),
+        };
+        let line = redact(&record.line, &state.redactions).into_owned();
+        let log = LogLine::at(line, source, record.stream, received_at, &state.parsers);
+        state.insert_log(log);
+        count += 1;
+    }
+    // The session is over, so are its requests
+    for source in state.requests.sources() {
+        state.end_requests(source, Stream::Stdout);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::tests::state;
+
+    /// A file of its own for each test, since they run at the same time
+    fn session_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("log-parser-{}-{}.ndjson", std::process::id(), name))
+    }
+
+    fn lines(state: &CliState) -> Vec<(String, usize, bool)> {
+        state
+            .history
+            .iter()
+            .map(|log| (log.line.clone(), log.source, log.generated))
+            .collect()
+    }
+
+    #[test]
+    fn saved_sessions_open_the_same_lines() {
+        let mut saved = state();
+        let api = saved.add_source("api");
+        let worker = saved.add_source("worker");
+        let time = DateTime::parse_from_rfc3339("2024-01-31T10:00:00.123+01:00")
+            .unwrap()
+            .with_timezone(&Local);
+        let logged = [
+            (api, r#"{"sql":"select 1"}"#),
+            (worker, "Error: boom\n    at main (index.js:1:1)"),
+            (api, r#"{"sql":"select 1"}"#),
+            (
+                api,
+                r#"{"level":30,"req":{"method":"GET","url":"/a"},"res":{"statusCode":200},"msg":"request completed"}"#,
+            ),
+        ];
+        for (source, line) in logged {
+            let log = LogLine::at(
+                line.to_string(),
+                source,
+                Stream::Stdout,
+                time,
+                &saved.parsers,
+            );
+            saved.insert_log(log);
+        }
+        // The N+1 warning is found again when opening the session
+        assert_eq!(saved.history.iter().filter(|log| log.generated).count(), 1);
+
+        let path = session_path("round-trip");
+        assert_eq!(save(&path, &saved).unwrap(), logged.len());
+        let file = std::fs::read_to_string(&path).unwrap();
+        let first: serde_json::Value = serde_json::from_str(file.lines().next().unwrap()).unwrap();
+        assert_eq!(first["time"], time.to_rfc3339());
+        assert_eq!(first["source"], "api");
+        assert_eq!(file.lines().count(), logged.len());
+
+        // Sources that already exist get the lines with the same label
+        let mut opened = state();
+        opened.add_source("worker");
+        assert_eq!(open(&path, &mut opened).unwrap(), logged.len());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(opened.find_source("api"), Some(1));
+        let swap = |source: usize| if source == api { 1 } else { 0 };
+        let expected: Vec<_> = lines(&saved)
+            .into_iter()
+            .map(|(line, source, generated)| (line, swap(source), generated))
+            .collect();
+        assert_eq!(lines(&opened), expected);
+        assert!(opened.history.iter().all(|log| log.received_at == time));
+    }
+
+    #[test]
+    fn malformed_records_are_errors() {
+        let path = session_path("malformed");
+        std::fs::write(
+            &path,
+            "{\"time\":\"2024-01-31T10:00:00+01:00\",\"source\":\"api\",\"stream\":\"stdout\",\"line\":\"hi\"}\n{\"time\":\n",
+        )
+        .unwrap();
+        let error = open(&path, &mut state()).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            error.starts_with("Invalid session record at line 2"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::core::{read_and_parse_logs, Context, Stream};
+
+// How often a followed file is checked for new data, truncation or rotation
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Somewhere logs can be read from
+pub enum Source {
+    /// A spawned command, both stdout and stderr are read
+    Command { label: String, command: Command },
+    /// A saved log file, read once from start to end
+    File(PathBuf),
+    /// Whatever is piped to our own stdin
+    Stdin,
+    /// A file that keeps being written to, read like `tail -F`
+    Follow(PathBuf),
+}
+
+impl Source {
+    /// Name used to tell this source apart from others in the output
+    pub fn label(&self) -> String {
+        match self {
+            Source::Command { label, .. } => label.clone(),
+            Source::File(path) | Source::Follow(path) => file_label(path),
+            Source::Stdin => "stdin".to_string(),
+        }
+    }
+
+    /// Starts reading the source in its own thread. Its streams are fed to the shared context
+    /// tagged with the index of the source they belong to
+    pub fn start(self, source: usize, context: &Context) -> Result<RunningSource, Box<dyn Error>> {
+        match self {
+            Source::Command { label, mut command } => {
+                let mut child = command
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| format!("Failed to start {}: {}", label, e))?;
+                let child_out =
+                    BufReader::new(child.stdout.take().ok_or("Failed to capture stdout")?);
+                let child_err =
+                    BufReader::new(child.stderr.take().ok_or("Failed to capture stderr")?);
+                let streams: Vec<(Stream, Box<dyn BufRead + Send>)> = vec![
+                    (Stream::Stdout, Box::new(child_out)),
+                    (Stream::Stderr, Box::new(child_err)),
+                ];
+
+                // So the main thread isn't hanging waiting for the server
+                let c = context.clone();
+                let reader = thread::spawn(move || read_and_parse_logs(streams, source, c));
+                Ok(RunningSource {
+                    child: Some(child),
+                    readers: vec![reader],
+                })
+            }
+            Source::File(path) => {
+                let file = File::open(&path)
+                    .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+                let reader = BufReader::new(file);
+                Ok(RunningSource::single(reader, source, context))
+            }
+            Source::Stdin => {
+                let reader = BufReader::new(io::stdin());
+                Ok(RunningSource::single(reader, source, context))
+            }
+            Source::Follow(path) => {
+                let follow = FollowReader::open(&path)
+                    .map_err(|e| format!("Failed to follow {}: {}", path.display(), e))?;
+                let reader = BufReader::new(follow);
+                Ok(RunningSource::single(reader, source, context))
+            }
+        }
+    }
+}
+
+/// A source that's being read, together with the threads reading it
+pub struct RunningSource {
+    child: Option<Child>,
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl RunningSource {
+    fn single<R: Read + Send + 'static>(
+        reader: BufReader<R>,
+        source: usize,
+        context: &Context,
+    ) -> RunningSource {
+        let c = context.clone();
+        let streams: Vec<(Stream, Box<dyn BufRead + Send>)> =
+            vec![(Stream::Stdout, Box::new(reader))];
+        let thread = thread::spawn(move || read_and_parse_logs(streams, source, c));
+        RunningSource {
+            child: None,
+            readers: vec![thread],
+        }
+    }
+
+    /// Waits until the source is exhausted (the command exits, the file ends) and all of its
+    /// output has been handled. Followed files never end
+    pub fn wait(self) -> Result<(), Box<dyn Error>> {
+        for reader in self.readers {
+            reader.join().map_err(|_| "Reader thread panicked")?;
+        }
+        if let Some(mut child) = self.child {
+            child.wait()?;
+        }
+        Ok(())
+    }
+
+    /// Stops reading right away, killing the command if there's one. Reader threads are left
+    /// to finish on their own
+    pub fn stop(self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut child) = self.child {
+            child.kill()?;
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the command for a `--cmd name=command` argument. The command is run through the shell
+/// so it can be passed as a single string, like `--cmd "api=npm run dev"`
+pub fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+fn file_label(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Reads a file forever, waiting for new data when reaching the end instead of returning EOF.
+/// Like `tail -F` it starts at the end of the file (use `--file` to read what's already there)
+/// and survives the file being truncated (starts over from the beginning) or
+/// rotated (reopens the path once a new file is there)
+struct FollowReader {
+    path: PathBuf,
+    file: File,
+    position: u64,
+}
+
+impl FollowReader {
+    fn open(path: &Path) -> io::Result<FollowReader> {
+        let mut file = File::open(path)?;
+        let position = file.seek(SeekFrom::End(0))?;
+        Ok(FollowReader {
+            path: path.to_path_buf(),
+            file,
+            position,
+        })
+    }
+
+    /// Checks if the file at our path is still the one we have open and if it shrunk
+    fn check_rotation(&mut self) -> io::Result<()> {
+        // The file might be missing for a moment while it's rotated, just try again later
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return Ok(());
+        };
+        if is_other_file(&self.file, &metadata) {
+            self.file = File::open(&self.path)?;
+            self.position = 0;
+        } else if metadata.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+            self.check_rotation()?;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_other_file(file: &File, metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match file.metadata() {
+        Ok(current) => current.ino() != metadata.ino() || current.dev() != metadata.dev(),
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_other_file(_file: &File, _metadata: &std::fs::Metadata) -> bool {
+    // No reliable way to tell files apart, only truncation is detected
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// A file of its own for each test, since they run at the same time
+    fn followed_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("log-parser-{}-{}.log", std::process::id(), name))
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = File::options().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    fn read(follow: &mut FollowReader) -> String {
+        let mut buf = [0; 64];
+        let read = follow.read(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..read]).to_string()
+    }
+
+    #[test]
+    fn starts_at_the_end_and_survives_truncation() {
+        let path = followed_path("truncate");
+        std::fs::write(&path, "already there\n").unwrap();
+        let mut follow = FollowReader::open(&path).unwrap();
+        append(&path, "new\n");
+        assert_eq!(read(&mut follow), "new\n");
+        // Truncated in place, like `> app.log` or logrotate's copytruncate
+        std::fs::write(&path, "a\n").unwrap();
+        assert_eq!(read(&mut follow), "a\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reopens_rotated_files() {
+        let path = followed_path("rotate");
+        let rotated = followed_path("rotate.1");
+        std::fs::write(&path, "").unwrap();
+        let mut follow = FollowReader::open(&path).unwrap();
+        append(&path, "before\n");
+        assert_eq!(read(&mut follow), "before\n");
+        std::fs::rename(&path, &rotated).unwrap();
+        std::fs::write(&path, "after rotating, longer than before\n").unwrap();
+        assert_eq!(read(&mut follow), "after rotating, longer than before\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+}
@@ -0,0 +1,701 @@
+use std::{error::Error, ops::Range, time::Duration};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use regex::Regex;
+
+use crate::commands::{self, Response};
+use crate::core::{display_line, CliState, Context, DisplayedLog, FormatType, LogLine};
+use crate::logs;
+
+// How long to wait for a key press before checking for new logs
+const TICK: Duration = Duration::from_millis(100);
+
+/// Takes over the terminal and shows logs in a full screen interface until the user quits
+pub fn run(context: &Context) -> Result<(), Box<dyn Error>> {
+    let mut terminal = ratatui::init();
+    let result = App::new().run(&mut terminal, context);
+    ratatui::restore();
+    result
+}
+
+/// A log that's shown in the log pane
+struct Entry {
+    log: LogLine,
+    lines: Vec<Line<'static>>,
+    // Used for search, everything that's shown without colors
+    text: String,
+}
+
+enum InputMode {
+    Normal,
+    Command,
+    Search,
+}
+
+struct App {
+    entries: Vec<Entry>,
+    // Value of `CliState::received` the last time entries were updated
+    seen: usize,
+    // Entries need to be built again from history, usually because settings changed
+    stale: bool,
+    list: ListState,
+    follow: bool,
+    show_details: bool,
+    mode: InputMode,
+    input: String,
+    search: Option<Regex>,
+    message: Option<String>,
+    /// Frame to open in the editor, which needs the terminal back first
+    open: Option<logs::Location>,
+    quit: bool,
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            entries: Vec::new(),
+            seen: 0,
+            stale: true,
+            list: ListState::default(),
+            follow: true,
+            show_details: false,
+            mode: InputMode::Normal,
+            input: String::new(),
+            search: None,
+            message: None,
+            open: None,
+            quit: false,
+        }
+    }
+
+    fn run(
+        mut self,
+        terminal: &mut DefaultTerminal,
+        context: &Context,
+    ) -> Result<(), Box<dyn Error>> {
+        while !self.quit {
+            self.sync(context);
+            terminal.draw(|frame| self.draw(frame, context))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key, context);
+                    }
+                }
+            }
+            if let Some(location) = self.open.take() {
+                ratatui::restore();
+                let opened = logs::open_in_editor(&location);
+                *terminal = ratatui::init();
+                self.message = Some(match opened {
+                    Ok(()) => format!("Opened {}", location),
+                    Err(e) => e,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Brings entries up to date with history, either adding what arrived since last time or
+    /// building everything again if needed
+    fn sync(&mut self, context: &Context) {
+        let mut lock = context.lock().unwrap();
+        if let Some(notice) = lock.notice.take() {
+            self.message = Some(notice);
+        }
+        if !self.stale && lock.received == self.seen {
+            return;
+        }
+        let count = if self.stale {
+            self.entries.clear();
+            lock.history.len()
+        } else {
+            lock.received - self.seen
+        };
+        for log in lock.history.last(count) {
+            if let Some(displayed) = display_line(log, &lock) {
+                self.entries.push(build_entry(
+                    log.clone(),
+                    displayed,
+                    &lock,
+                    self.search.as_ref(),
+                ));
+            }
+        }
+        self.seen = lock.received;
+        self.stale = false;
+
+        // Entries are a subset of history, so there's no point in keeping more than that
+        if self.entries.len() > lock.history.len() {
+            let removed = self.entries.len() - lock.history.len();
+            self.entries.drain(0..removed);
+            if let Some(selected) = self.list.selected() {
+                self.list.select(Some(selected.saturating_sub(removed)));
+            }
+        }
+        if self.follow || self.list.selected().is_none() {
+            self.list.select(self.entries.len().checked_sub(1));
+        } else if let Some(selected) = self.list.selected() {
+            self.list
+                .select(Some(selected.min(self.entries.len().saturating_sub(1))));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, context: &Context) {
+        let [main, status, input] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (logs_area, details_area) = if self.show_details {
+            let [logs_area, details_area] =
+                Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .areas(main);
+            (logs_area, Some(details_area))
+        } else {
+            (main, None)
+        };
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|e| ListItem::new(e.lines.clone()))
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(" Logs "))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+        frame.render_stateful_widget(list, logs_area, &mut self.list);
+
+        if let Some(details_area) = details_area {
+            let details = self
+                .list
+                .selected()
+                .and_then(|i| self.entries.get(i))
+                .map(|e| details_for(&e.log))
+                .unwrap_or_default();
+            let paragraph = Paragraph::new(details)
+                .block(Block::default().borders(Borders::ALL).title(" Details "))
+                .wrap(Wrap { trim: false });
+            frame.render_widget(paragraph, details_area);
+        }
+
+        let status_line = {
+            let lock = context.lock().unwrap();
+            self.status(&lock)
+        };
+        frame.render_widget(
+            Paragraph::new(status_line).style(Style::default().bg(Color::Blue).fg(Color::White)),
+            status,
+        );
+
+        let input_line = match self.mode {
+            InputMode::Command => format!(":{}", self.input),
+            InputMode::Search => format!("/{}", self.input),
+            InputMode::Normal => self.message.clone().unwrap_or_else(|| {
+                "q quit  : command  / search  n/N next/prev  space pause/follow  enter details"
+                    .to_string()
+            }),
+        };
+        frame.render_widget(Paragraph::new(input_line), input);
+    }
+
+    fn status(&self, state: &CliState) -> String {
+        let mut parts = vec![
+            if self.follow { "FOLLOW" } else { "PAUSED" }.to_string(),
+            format!(
+                "format: {}",
+                match state.format_type {
+                    FormatType::Compact => "compact",
+                    FormatType::Detailed => "detailed",
+                    FormatType::Raw => "raw",
+                }
+            ),
+        ];
+        if let Some(level) = state.min_level {
+            parts.push(format!("level: {}", level));
+        }
+        if let Some(include) = &state.text_filter.include {
+            parts.push(format!("filter: {}", include));
+        }
+        if let Some(exclude) = &state.text_filter.exclude {
+            parts.push(format!("exclude: {}", exclude));
+        }
+        if let Some(query) = &state.query {
+            parts.push(format!("query: {}", query));
+        }
+        if let Some(focus) = state.focus.and_then(|f| state.sources.get(f)) {
+            parts.push(format!("focus: {}", focus.label));
+        }
+        let mut detected: Vec<&str> = Vec::new();
+        for parser in state
+            .detections
+            .values()
+            .filter_map(|d| d.parser.as_deref())
+        {
+            if !detected.contains(&parser) {
+                detected.push(parser);
+            }
+        }
+        if !detected.is_empty() {
+            parts.push(format!("parser: {}", detected.join(", ")));
+        }
+        if let Some(search) = &self.search {
+            parts.push(format!("search: {}", search));
+        }
+        parts.push(format!(
+            "{} shown / {} received",
+            self.entries.len(),
+            state.received
+        ));
+        format!(" {}", parts.join(" | "))
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, context: &Context) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match self.mode {
+            InputMode::Normal => self.handle_normal_key(key),
+            InputMode::Command | InputMode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.mode = InputMode::Normal;
+                    self.input.clear();
+                }
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Enter => {
+                    let input = std::mem::take(&mut self.input);
+                    match self.mode {
+                        InputMode::Command => self.run_command(&input, context),
+                        _ => self.start_search(&input),
+                    }
+                    self.mode = InputMode::Normal;
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn handle_normal_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char(':') => {
+                self.mode = InputMode::Command;
+                self.message = None;
+            }
+            KeyCode::Char('/') => {
+                self.mode = InputMode::Search;
+                self.message = None;
+            }
+            KeyCode::Char('n') => self.find_match(true),
+            KeyCode::Char('N') => self.find_match(false),
+            KeyCode::Char(' ') | KeyCode::Char('p') => {
+                self.follow = !self.follow;
+                if self.follow {
+                    self.list.select(self.entries.len().checked_sub(1));
+                }
+            }
+            KeyCode::Enter => self.show_details = !self.show_details,
+            KeyCode::Esc => self.show_details = false,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-20),
+            KeyCode::PageDown => self.move_selection(20),
+            KeyCode::Home | KeyCode::Char('g') => {
+                self.follow = false;
+                self.list.select(if self.entries.is_empty() {
+                    None
+                } else {
+                    Some(0)
+                });
+            }
+            KeyCode::End | KeyCode::Char('G') => {
+                self.follow = true;
+                self.list.select(self.entries.len().checked_sub(1));
+            }
+            _ => {}
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let last = self.entries.len() - 1;
+        let current = self.list.selected().unwrap_or(last);
+        let next = current.saturating_add_signed(delta).min(last);
+        // Scrolling up means the user wants to look at something, stop following new logs
+        self.follow = next == last && delta > 0;
+        self.list.select(Some(next));
+    }
+
+    fn run_command(&mut self, input: &str, context: &Context) {
+        self.message = match commands::execute(input, context) {
+            Response::Nothing => Some(format!("Unknown command {}", input)),
+            Response::Message(message) => Some(message),
+            Response::Replay => {
+                self.follow = true;
+                Some("Replaying".to_string())
+            }
+            Response::Open(location) => {
+                self.open = Some(location);
+                None
+            }
+            Response::Exit => {
+                self.quit = true;
+                None
+            }
+        };
+        // Most commands change what's shown, so start over from history
+        self.stale = true;
+    }
+
+    fn start_search(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            self.search = None;
+            self.stale = true;
+            return;
+        }
+        // Anything that isn't a valid regex is searched as plain text
+        let regex = Regex::new(pattern).or_else(|_| Regex::new(&regex::escape(pattern)));
+        match regex {
+            Ok(regex) => {
+                self.search = Some(regex);
+                self.stale = true;
+                self.find_match(true);
+            }
+            Err(e) => self.message = Some(format!("Invalid search: {}", e)),
+        }
+    }
+
+    /// Selects the next (or previous) entry matching the search, wrapping around
+    fn find_match(&mut self, forward: bool) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let count = self.entries.len();
+        if count == 0 {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0);
+        let found = (1..=count)
+            .map(|step| {
+                if forward {
+                    (current + step) % count
+                } else {
+                    (current + count - step) % count
+                }
+            })
+            .find(|&i| search.is_match(&self.entries[i].text));
+        match found {
+            Some(i) => {
+                self.follow = false;
+                self.list.select(Some(i));
+                self.message = None;
+            }
+            None => self.message = Some(format!("No match for {}", search)),
+        }
+    }
+}
+
+/// Builds the lines shown for a log, the same way it would be printed in the terminal
+fn build_entry(
+    log: LogLine,
+    displayed: DisplayedLog,
+    state: &CliState,
+    search: Option<&Regex>,
+) -> Entry {
+    let highlights: Vec<&Regex> = state.text_filter.highlight.iter().chain(search).collect();
+    let mut prefix = Vec::new();
+    if let Some((label, color)) = state.label_for(log.source) {
+        prefix.push(Span::styled(
+            format!("{} ", label),
+            Style::default().fg(to_tui_color(color)),
+        ));
+    }
+
+    let mut lines = Vec::new();
+    let mut text = String::new();
+    match displayed {
+        DisplayedLog::Raw(line) => {
+            let mut spans = prefix;
+            spans.extend(highlight_spans(&line, Style::default(), &highlights));
+            lines.push(Line::from(spans));
+            text.push_str(&line);
+        }
+        DisplayedLog::Formatted(formatted) => {
+            let mut first = prefix;
+            first.push(Span::styled(
+                formatted
+                    .date
+                    .map(|date| format!("{} ", date.format(&state.date_format)))
+                    .unwrap_or_default(),
+                Style::default().fg(Color::Cyan),
+            ));
+            if let Some(level) = formatted.level {
+                first.push(Span::styled(
+                    format!("{:<5} ", level.name().to_uppercase()),
+                    Style::default().fg(to_tui_color(level.color())),
+                ));
+            }
+            let msg_style = Style::default().fg(to_tui_color(
+                formatted.color_overwrite.unwrap_or(termcolor::Color::White),
+            ));
+            let msg = formatted.msg.trim();
+            // Colors point into the untrimmed message, make them point into each line instead
+            let trimmed = formatted.msg.len() - formatted.msg.trim_start().len();
+            let line_spans = |offset: usize, line: &str| {
+                let start = trimmed + offset;
+                let colors: Vec<(Range<usize>, Style)> = formatted
+                    .msg_colors
+                    .iter()
+                    .filter(|(range, _)| range.start >= start && range.end <= start + line.len())
+                    .map(|(range, color)| {
+                        (
+                            range.start - start..range.end - start,
+                            Style::default().fg(to_tui_color(*color)),
+                        )
+                    })
+                    .collect();
+                colored_spans(line, msg_style, &colors, &highlights)
+            };
+            let mut msg_lines = lines_with_offsets(msg).into_iter();
+            if let Some((offset, line)) = msg_lines.next() {
+                first.extend(line_spans(offset, line));
+            }
+            lines.push(Line::from(first));
+            for (offset, line) in msg_lines {
+                lines.push(Line::from(line_spans(offset, line)));
+            }
+            text.push_str(msg);
+
+            if let Some(stack) = &formatted.stack {
+                for (kind, line) in stack.shown(state.all_frames) {
+                    let style = match kind {
+                        logs::LineKind::Error => Style::default().fg(Color::Red),
+                        logs::LineKind::Dependency | logs::LineKind::Folded => {
+                            msg_style.add_modifier(Modifier::DIM)
+                        }
+                        logs::LineKind::Frame | logs::LineKind::Other => msg_style,
+                    };
+                    lines.push(Line::from(highlight_spans(&line, style, &highlights)));
+                    text.push('\n');
+                    text.push_str(&line);
+                }
+            }
+
+            if let Some(extra) = &formatted.extra {
+                let json = serde_json::to_string_pretty(extra).unwrap_or_default();
+                let style = Style::default().fg(Color::Cyan);
+                for line in json.lines() {
+                    lines.push(Line::from(highlight_spans(line, style, &highlights)));
+                }
+                text.push('\n');
+                text.push_str(&json);
+            }
+        }
+    }
+
+    Entry { log, lines, text }
+}
+
+/// The lines of a text (without their line ending) and where each of them starts
+fn lines_with_offsets(text: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    text.split_inclusive('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            (start, line.trim_end_matches(['\r', '\n']))
+        })
+        .collect()
+}
+
+/// Splits text in spans so every match of the given patterns stands out
+fn highlight_spans(text: &str, style: Style, highlights: &[&Regex]) -> Vec<Span<'static>> {
+    let mut ranges: Vec<(usize, usize)> = highlights
+        .iter()
+        .flat_map(|regex| regex.find_iter(text).map(|m| (m.start(), m.end())))
+        .filter(|(start, end)| start < end)
+        .collect();
+    ranges.sort();
+
+    let highlighted = Style::default()
+        .fg(Color::Black)
+        .bg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (start, end) in ranges {
+        if start < last {
+            continue;
+        }
+        if start > last {
+            spans.push(Span::styled(text[last..start].to_string(), style));
+        }
+        spans.push(Span::styled(text[start..end].to_string(), highlighted));
+        last = end;
+    }
+    if last < text.len() || spans.is_empty() {
+        spans.push(Span::styled(text[last..].to_string(), style));
+    }
+    spans
+}
+
+/// Like `highlight_spans`, but parts of the text can have their own style
+fn colored_spans(
+    text: &str,
+    style: Style,
+    colors: &[(Range<usize>, Style)],
+    highlights: &[&Regex],
+) -> Vec<Span<'static>> {
+    if colors.is_empty() {
+        return highlight_spans(text, style, highlights);
+    }
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (range, color) in colors {
+        if range.start < last {
+            continue;
+        }
+        spans.extend(highlight_spans(&text[last..range.start], style, highlights));
+        spans.extend(highlight_spans(&text[range.clone()], *color, highlights));
+        last = range.end;
+    }
+    spans.extend(highlight_spans(&text[last..], style, highlights));
+    spans.retain(|span| !span.content.is_empty());
+    spans
+}
+
+/// Every field of the log as pretty JSON, or the raw line if it couldn't be parsed
+fn details_for(log: &LogLine) -> String {
+    match &log.parsed {
+        Some(parsed) => serde_json::to_string_pretty(parsed.fields())
+            .unwrap_or_else(|_| log.line.trim_end().to_string()),
+        None => log.line.trim_end().to_string(),
+    }
+}
+
+fn to_tui_color(color: termcolor::Color) -> Color {
+    if !logs::color_enabled() {
+        return Color::Reset;
+    }
+    match color {
+        termcolor::Color::Black => Color::Black,
+        termcolor::Color::Blue => Color::Blue,
+        termcolor::Color::Green => Color::Green,
+        termcolor::Color::Red => Color::Red,
+        termcolor::Color::Cyan => Color::Cyan,
+        termcolor::Color::Magenta => Color::Magenta,
+        termcolor::Color::Yellow => Color::Yellow,
+        termcolor::Color::White => Color::White,
+        termcolor::Color::Ansi256(i) => Color::Indexed(i),
+        termcolor::Color::Rgb(r, g, b) => Color::Rgb(r, g, b),
+        _ => Color::Reset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Stream;
+
+    /// The text of each span and whether it's highlighted
+    fn contents(spans: &[Span]) -> Vec<(String, bool)> {
+        spans
+            .iter()
+            .map(|span| {
+                (
+                    span.content.to_string(),
+                    span.style.bg == Some(Color::Yellow),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn highlights_every_match() {
+        let digits = Regex::new(r"\d+").unwrap();
+        let user = Regex::new("user 4").unwrap();
+        let spans = highlight_spans("user 42 logged in 3 times", Style::default(), &[&digits]);
+        assert_eq!(
+            contents(&spans),
+            vec![
+                ("user ".to_string(), false),
+                ("42".to_string(), true),
+                (" logged in ".to_string(), false),
+                ("3".to_string(), true),
+                (" times".to_string(), false),
+            ]
+        );
+        // Overlapping matches don't repeat text
+        let spans = highlight_spans("user 42", Style::default(), &[&user, &digits]);
+        assert_eq!(
+            contents(&spans),
+            vec![("user 4".to_string(), true), ("2".to_string(), false)]
+        );
+        let spans = highlight_spans("", Style::default(), &[&digits]);
+        assert_eq!(contents(&spans), vec![(String::new(), false)]);
+    }
+
+    #[test]
+    fn keeps_colors_and_highlights_within_them() {
+        let red = Style::default().fg(Color::Red);
+        let one = Regex::new("1").unwrap();
+        let spans = colored_spans("SELECT 1 FROM t", Style::default(), &[(0..8, red)], &[&one]);
+        assert_eq!(
+            contents(&spans),
+            vec![
+                ("SELECT ".to_string(), false),
+                ("1".to_string(), true),
+                (" FROM t".to_string(), false),
+            ]
+        );
+        assert_eq!(spans[0].style.fg, Some(Color::Red));
+        assert_eq!(spans[2].style.fg, None);
+    }
+
+    #[test]
+    fn finds_where_each_line_starts() {
+        assert_eq!(
+            lines_with_offsets("a\r\nbc\n\nd"),
+            vec![(0, "a"), (3, "bc"), (6, ""), (7, "d")]
+        );
+        assert!(lines_with_offsets("").is_empty());
+    }
+
+    #[test]
+    fn search_wraps_around() {
+        let mut app = App::new();
+        for text in ["error a", "ok", "error b"] {
+            app.entries.push(Entry {
+                log: LogLine::new(text.to_string(), None, None, 0, Stream::Stdout),
+                lines: Vec::new(),
+                text: text.to_string(),
+            });
+        }
+        app.list.select(Some(2));
+        app.search = Some(Regex::new("error").unwrap());
+        app.find_match(true);
+        assert_eq!(app.list.selected(), Some(0));
+        assert!(!app.follow);
+        app.find_match(false);
+        assert_eq!(app.list.selected(), Some(2));
+        app.search = Some(Regex::new("warn").unwrap());
+        app.find_match(true);
+        assert_eq!(app.list.selected(), Some(2));
+        assert_eq!(app.message.as_deref(), Some("No match for warn"));
+    }
+}